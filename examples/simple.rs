@@ -1,10 +1,12 @@
 use bevy::prelude::*;
-use bevy_asefile::{AsepriteAtlas, AsepriteBundle, AsepritePlugin};
+use bevy_asefile::{
+    AsepriteAtlas, AsepriteBundle, AsepriteNinepatch, AsepriteNinepatchBundle, AsepritePlugin,
+};
 
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
-        .add_plugin(AsepritePlugin)
+        .add_plugin(AsepritePlugin::default())
         .add_startup_system(setup)
         .run();
 }
@@ -74,4 +76,23 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         },
         ..Default::default()
     });
+
+    /* stretched ninepatch */
+    commands.spawn(AsepriteNinepatchBundle {
+        aseprite: asset_server.load("ui.aseprite"),
+        aseprite_atlas: AsepriteAtlas {
+            slice: Some("SpeechBubble"),
+            ..Default::default()
+        },
+        ninepatch: AsepriteNinepatch {
+            size: Vec2::new(60., 20.),
+            ..Default::default()
+        },
+        transform: Transform {
+            scale: Vec3::splat(4.),
+            translation: Vec3::new(0., -120., 0.),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
 }
@@ -1,10 +1,12 @@
 use bevy::prelude::*;
-use bevy_asefile::{AsepriteAtlas, AsepriteBundle, AsepritePlugin, AsepriteUiBundle};
+use bevy_asefile::{
+    AsepriteAtlas, AsepriteBundle, AsepritePlugin, AsepriteUiBundle, AsepriteUiLayout,
+};
 
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
-        .add_plugin(AsepritePlugin)
+        .add_plugin(AsepritePlugin::default())
         .add_startup_system(setup)
         .run();
 }
@@ -39,9 +41,12 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     style: Style {
                         justify_content: JustifyContent::Center,
                         align_items: AlignItems::Center,
-                        padding: UiRect::all(Val::Px(20.0)),
                         ..Default::default()
                     },
+                    layout: AsepriteUiLayout {
+                        padding: true,
+                        min_size: true,
+                    },
                     ..Default::default()
                 })
                 .with_children(|builder| {
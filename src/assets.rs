@@ -1,5 +1,10 @@
+use crate::blend::blend_image;
+use crate::components::AsepriteAtlas;
+use crate::mask::AlphaMask;
+use crate::outline::trace_outlines;
+use crate::user_data::{read_tile_user_data, AsepriteTileUserData};
 use asefile::Tag;
-use asefile::{AsepriteFile, LayerType};
+use asefile::{AsepriteFile, BlendMode, Layer, LayerFlags, LayerType};
 use bevy::{
     asset::{AssetLoader, LoadedAsset},
     prelude::*,
@@ -14,36 +19,113 @@ use std::time::Duration;
 
 #[derive(Hash, PartialEq, Eq, Debug, Default)]
 pub struct AtlasKey {
-    layer: Option<u32>,
-    slice: Option<u32>,
-    frame: u32,
-    ninepatch: Option<u8>,
+    pub(crate) layer: Option<u32>,
+    pub(crate) slice: Option<u32>,
+    pub(crate) frame: u32,
+    pub(crate) ninepatch: Option<u8>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct SliceSegment {
     pub from_frame: usize,
+    pub origin: Vec2,
     pub size: Vec2,
+    pub pivot: Option<Vec2>,
     pub ninepatch_center: Option<Rect>,
 }
 
-#[derive(Debug, Default)]
+impl SliceSegment {
+    pub fn rect(&self) -> Rect {
+        Rect::from_corners(self.origin, self.origin + self.size)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Slice {
     pub name: String,
+    pub user_data: Option<String>,
     pub segments: Vec<SliceSegment>,
 }
 
+impl Slice {
+    /// The key active on `frame`, or `None` before the first key or where the key is empty.
+    pub fn segment(&self, frame: usize) -> Option<&SliceSegment> {
+        let index = self
+            .segments
+            .iter()
+            .position(|key| key.from_frame > frame)
+            .unwrap_or(self.segments.len());
+        let segment = self.segments.get(index.checked_sub(1)?)?;
+        (segment.size != Vec2::ZERO).then_some(segment)
+    }
+}
+
+#[derive(Debug)]
+pub struct AsepriteTileset {
+    pub id: u32,
+    pub name: String,
+    pub tile_size: UVec2,
+    pub tile_count: u32,
+    pub tile_user_data: Vec<Option<AsepriteTileUserData>>,
+    pub atlas: Handle<TextureAtlas>,
+}
+
+impl AsepriteTileset {
+    pub fn tile_user_data(&self, tile: u32) -> Option<&AsepriteTileUserData> {
+        self.tile_user_data.get(tile as usize)?.as_ref()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TilemapTile {
+    pub id: u32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotate_90cw: bool,
+}
+
+impl TilemapTile {
+    pub fn is_empty(&self) -> bool {
+        self.id == 0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AsepriteTilemap {
+    pub layer: u32,
+    pub tileset: u32,
+    pub size: UVec2,
+    pub frames: Vec<Vec<TilemapTile>>,
+}
+
+impl AsepriteTilemap {
+    pub fn tile(&self, frame: u32, x: u32, y: u32) -> TilemapTile {
+        self.frames[frame as usize][(y * self.size.x + x) as usize]
+    }
+}
+
 #[derive(Debug, TypeUuid, TypePath)]
 #[uuid = "f10252cf-4b15-43a6-a8b2-811c408b2758"]
 pub struct Aseprite {
     pub path: PathBuf,
+    pub size: Vec2,
     pub layers: Vec<String>,
+    pub layer_blend_modes: Vec<BlendMode>,
+    pub layer_opacities: Vec<u8>,
+    pub layer_parents: Vec<Option<u32>>,
+    /// Whether each layer is baked into full frames under the loader settings.
+    pub layer_in_frames: Vec<bool>,
     pub tags: Vec<Tag>,
     pub slices: Vec<Slice>,
     pub frame_durations: Vec<Duration>,
     pub num_frames: u32,
     pub atlas_indexes: HashMap<AtlasKey, u32>,
     pub atlas: Handle<TextureAtlas>,
+    pub alpha_masks: Vec<AlphaMask>,
+    pub alpha_threshold: u8,
+    pub outlines: HashMap<u32, Vec<Vec<Vec2>>>,
+    pub tilesets: Vec<AsepriteTileset>,
+    pub tilemaps: Vec<AsepriteTilemap>,
 }
 
 impl Aseprite {
@@ -82,7 +164,32 @@ impl Aseprite {
             .iter()
             .position(|key| key.from_frame > frame)
             .unwrap_or(slice.segments.len())
-            - 1]
+            .saturating_sub(1)]
+    }
+    pub fn find_slice(&self, name: &str) -> Option<&Slice> {
+        self.slices.iter().find(|slice| slice.name == name)
+    }
+    pub fn slice_index(&self, name: &str, frame: u32) -> Option<u32> {
+        self.atlas_indexes
+            .get(&AtlasKey {
+                layer: None,
+                slice: Some(self.slice_id(name)),
+                frame,
+                ninepatch: None,
+            })
+            .copied()
+    }
+    /// The canvas-space pivot of the slice on `frame`, if its key has one.
+    pub fn slice_pivot(&self, name: &str, frame: usize) -> Option<Vec2> {
+        let segment = self.find_slice(name)?.segment(frame)?;
+        segment.pivot.map(|pivot| segment.origin + pivot)
+    }
+    /// The canvas-space region shown by an atlas selection on `frame`.
+    pub fn atlas_region(&self, ase_atlas: &AsepriteAtlas, frame: usize) -> Rect {
+        match ase_atlas.slice {
+            Some(slice) => self.slice(slice, frame).rect(),
+            None => Rect::from_corners(Vec2::ZERO, self.size),
+        }
     }
     pub fn slice_id(&self, name: &str) -> u32 {
         self.slices
@@ -98,16 +205,67 @@ impl Aseprite {
                 )
             })
     }
+    pub fn tileset(&self, id: u32) -> &AsepriteTileset {
+        self.tilesets
+            .iter()
+            .find(|tileset| tileset.id == id)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Tileset `{}` is not exists at `{}`",
+                    id,
+                    self.path.display()
+                )
+            })
+    }
+    pub fn tilemap(&self, layer_name: &str) -> &AsepriteTilemap {
+        let layer = self.layer_id(layer_name);
+        self.tilemaps
+            .iter()
+            .find(|tilemap| tilemap.layer == layer)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Layer `{}` is not a tilemap at `{}`",
+                    layer_name,
+                    self.path.display()
+                )
+            })
+    }
+    pub fn frame_index(&self, frame: u32) -> Option<u32> {
+        self.atlas_indexes
+            .get(&AtlasKey {
+                layer: None,
+                slice: None,
+                frame,
+                ninepatch: None,
+            })
+            .copied()
+    }
+    pub fn frame_range(&self, tag_name: Option<&str>) -> Range<u32> {
+        match tag_name.map(|name| self.tag(name)) {
+            Some(tag) => tag.from_frame()..(tag.to_frame() + 1),
+            None => 0..self.num_frames,
+        }
+    }
+    /// Traced outlines of an atlas entry, in the entry's pixel space. Empty unless the entry's
+    /// layer or slice is listed in the loader's outline settings.
+    pub fn outlines(&self, index: usize) -> &[Vec<Vec2>] {
+        self.outlines
+            .get(&(index as u32))
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
     pub fn frame_duration(&self, frame: usize) -> Duration {
         self.frame_durations[frame]
     }
+    /// The atlas entries of a selection over a tag, or `None` when the selection has no atlas
+    /// entries, such as a layer excluded by the loader settings.
     pub fn atlas_range(
         &self,
         layer_name: Option<&str>,
         tag_name: Option<&str>,
         slice_name: Option<&str>,
         ninepatch: Option<u8>,
-    ) -> Range<u32> {
+    ) -> Option<Range<u32>> {
         let tag = tag_name.map(|name| self.tag(name));
         let layer = layer_name.map(|name| self.layer_id(name));
         let slice = slice_name.map(|name| self.slice_id(name));
@@ -124,22 +282,82 @@ impl Aseprite {
             Some(tag) => (tag.from_frame(), tag.to_frame()),
             None => (0, self.num_frames - 1),
         };
-        self.atlas_indexes[&AtlasKey {
+        let start = self.atlas_indexes.get(&AtlasKey {
             layer,
             slice,
             frame: start_frame,
             ninepatch,
-        }]..(self.atlas_indexes[&AtlasKey {
+        })?;
+        let end = self.atlas_indexes.get(&AtlasKey {
             layer,
             slice,
             frame: end_frame,
             ninepatch,
-        }] + 1u32)
+        })?;
+        Some(*start..(*end + 1u32))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AsepriteLoaderSettings {
+    /// Bake hidden layers into full-frame images.
+    pub hidden_layers_in_frames: bool,
+    /// Keep atlas entries for hidden layers so they can be addressed by `AsepriteAtlas::layer`.
+    pub hidden_layers_in_layers: bool,
+    /// Bake hidden child layers into group composites.
+    pub hidden_layers_in_groups: bool,
+    /// Treat reference layers as regular layers instead of hidden ones.
+    pub include_reference_layers: bool,
+    /// Layers whose name starts with any of these prefixes are treated as hidden.
+    pub excluded_layer_prefixes: Vec<String>,
+    /// Pixels with an alpha above this are opaque in `Aseprite::alpha_masks`.
+    pub alpha_threshold: u8,
+    /// Layers whose atlas entries get traced into `Aseprite::outlines`.
+    pub outline_layers: Vec<String>,
+    /// Slices whose atlas entries get traced into `Aseprite::outlines`.
+    pub outline_slices: Vec<String>,
+    /// How far, in pixels, a simplified outline may stray from the traced contour.
+    pub outline_tolerance: f32,
+}
+
+impl Default for AsepriteLoaderSettings {
+    fn default() -> Self {
+        Self {
+            hidden_layers_in_frames: false,
+            hidden_layers_in_layers: true,
+            hidden_layers_in_groups: false,
+            include_reference_layers: false,
+            excluded_layer_prefixes: Vec::new(),
+            alpha_threshold: 0,
+            outline_layers: Vec::new(),
+            outline_slices: Vec::new(),
+            outline_tolerance: 1.0,
+        }
+    }
+}
+
+impl AsepriteLoaderSettings {
+    pub fn is_hidden(&self, layer: &Layer) -> bool {
+        !layer.is_visible()
+            || (!self.include_reference_layers && layer.flags().contains(LayerFlags::REFERENCE))
+            || self
+                .excluded_layer_prefixes
+                .iter()
+                .any(|prefix| layer.name().starts_with(prefix.as_str()))
+    }
+    fn is_hidden_in_tree(&self, layer: &Layer) -> bool {
+        self.is_hidden(layer)
+            || layer
+                .parent()
+                .map(|parent| self.is_hidden_in_tree(&parent))
+                .unwrap_or(false)
     }
 }
 
 #[derive(Debug, Default)]
-pub struct AsepriteLoader;
+pub struct AsepriteLoader {
+    pub settings: AsepriteLoaderSettings,
+}
 
 impl AssetLoader for AsepriteLoader {
     fn load<'a>(
@@ -150,29 +368,22 @@ impl AssetLoader for AsepriteLoader {
         Box::pin(async move {
             debug!("Loading aseprite at {:?}", load_context.path());
             let asefile = AsepriteFile::read(bytes)?;
+            let settings = &self.settings;
 
             let padding = Vec2::new(1.0, 1.0);
             let num_frames = asefile.num_frames();
             let num_layers = asefile.num_layers();
             let cel_width = asefile.width() as u32 + (padding.x as u32);
-            let cel_height = asefile.width() as u32 + (padding.y as u32);
-            let non_empty_cels: Vec<_> = (0..num_frames * num_layers)
+            let cel_height = asefile.height() as u32 + (padding.y as u32);
+            // Every frame of an addressable layer gets an entry, left transparent where its cel
+            // is empty, so each layer's frames stay contiguous in the atlas.
+            let layer_cels: Vec<_> = (0..num_frames * num_layers)
                 .filter(|i| {
-                    matches!(asefile.layer(i / num_frames).layer_type(), LayerType::Group)
-                        || !asefile.cel(i % num_frames, i / num_frames).is_empty()
+                    let layer = asefile.layer(i / num_frames);
+                    settings.hidden_layers_in_layers || !settings.is_hidden_in_tree(&layer)
                 })
                 .map(|i| asefile.cel(i % num_frames, i / num_frames))
                 .collect();
-            let non_empty_cel_images: Vec<_> = non_empty_cels
-                .iter()
-                .map(|cel| {
-                    if !cel.is_empty() {
-                        Some(cel.image())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
             let mut atlas_indexes = HashMap::from_iter(
                 (0..num_frames)
                     .map(|i| {
@@ -186,7 +397,7 @@ impl AssetLoader for AsepriteLoader {
                             i,
                         )
                     })
-                    .chain(non_empty_cels.iter().enumerate().map(|(i, cel)| {
+                    .chain(layer_cels.iter().enumerate().map(|(i, cel)| {
                         (
                             AtlasKey {
                                 layer: Some(cel.layer()),
@@ -199,60 +410,38 @@ impl AssetLoader for AsepriteLoader {
                     })),
             );
 
-            let num_rows = ((non_empty_cels.len() as u32 + num_frames) as f32).sqrt() as u32 + 1;
+            let num_rows = ((layer_cels.len() as u32 + num_frames) as f32).sqrt() as u32 + 1;
             let mut buffer = image::RgbaImage::new(num_rows * cel_width, num_rows * cel_height);
 
             for index in 0u32..num_frames {
                 buffer.copy_from(
-                    &asefile.frame(index).image(),
+                    &compose_layers(&asefile, None, index, &|layer| {
+                        settings.hidden_layers_in_frames || !settings.is_hidden(layer)
+                    }),
                     index % num_rows * cel_width,
                     index / num_rows * cel_height,
                 )?;
             }
-            for (i, cel) in non_empty_cels.iter().enumerate() {
-                if let Some(image) = &non_empty_cel_images[i] {
-                    let index = i as u32 + num_frames;
-                    buffer.copy_from(
-                        image,
-                        index % num_rows * cel_width,
-                        index / num_rows * cel_height,
-                    )?;
-
-                    let mut parent = asefile.layer(cel.layer()).parent().map(|l| l.id());
-                    while let Some(parent_layer) = parent {
-                        let parent_cel = asefile.cel(cel.frame(), parent_layer);
-                        let index = atlas_indexes[&AtlasKey {
-                            layer: Some(parent_cel.layer()),
-                            frame: parent_cel.frame(),
-                            slice: None,
-                            ninepatch: None,
-                        }];
-                        image::imageops::overlay(
-                            &mut buffer,
-                            image,
-                            index % num_rows * cel_width,
-                            index / num_rows * cel_height,
-                        );
-                        parent = asefile.layer(parent_layer).parent().map(|l| l.id());
-                    }
-                }
+            for (i, cel) in layer_cels.iter().enumerate() {
+                let index = i as u32 + num_frames;
+                let image = if matches!(asefile.layer(cel.layer()).layer_type(), LayerType::Group) {
+                    compose_layers(&asefile, Some(cel.layer()), cel.frame(), &|layer| {
+                        settings.hidden_layers_in_groups || !settings.is_hidden(layer)
+                    })
+                } else if cel.is_empty() {
+                    continue;
+                } else {
+                    cel.image()
+                };
+                buffer.copy_from(
+                    &image,
+                    index % num_rows * cel_width,
+                    index / num_rows * cel_height,
+                )?;
             }
 
-            let texture = load_context.set_labeled_asset(
-                "texture",
-                LoadedAsset::new(Image::new(
-                    Extent3d {
-                        width: buffer.width(),
-                        height: buffer.height(),
-                        depth_or_array_layers: 1,
-                    },
-                    TextureDimension::D2,
-                    buffer.into_raw(),
-                    TextureFormat::Rgba8UnormSrgb,
-                )),
-            );
             let mut atlas = TextureAtlas::from_grid(
-                texture,
+                Handle::default(),
                 Vec2::new(cel_width as f32 - padding.x, cel_height as f32 - padding.y),
                 num_rows as usize,
                 num_rows as usize,
@@ -265,11 +454,12 @@ impl AssetLoader for AsepriteLoader {
                     continue;
                 }
                 for frame in 0u32..num_frames {
+                    // Frames before the first key use the first key.
                     let key_index = keys
                         .iter()
                         .position(|key| key.from_frame > frame)
                         .unwrap_or(keys.len())
-                        - 1;
+                        .saturating_sub(1);
                     let key = &keys[key_index];
                     let rect = atlas.textures[atlas_indexes[&AtlasKey {
                         layer: None,
@@ -294,45 +484,41 @@ impl AssetLoader for AsepriteLoader {
                         index as u32,
                     );
                 }
-                if let Some(slice9) = &keys[0].slice9 {
+                if keys.iter().any(|key| key.slice9.is_some()) {
                     for ninepatch in 0u8..9 {
                         for frame in 0u32..num_frames {
+                            let key_index = keys
+                                .iter()
+                                .position(|key| key.from_frame > frame)
+                                .unwrap_or(keys.len())
+                                .saturating_sub(1);
+                            let key = &keys[key_index];
                             let rect = atlas.textures[atlas_indexes[&AtlasKey {
                                 layer: None,
                                 frame,
                                 slice: Some(slice),
                                 ninepatch: None,
                             }] as usize];
-                            let (x1, x2) = match ninepatch % 3 {
-                                0 => (rect.min.x, rect.min.x + slice9.center_x as f32),
-                                1 => (
-                                    rect.min.x + slice9.center_x as f32,
-                                    rect.min.x
-                                        + slice9.center_x as f32
-                                        + slice9.center_width as f32,
-                                ),
-                                2 => (
-                                    rect.min.x
-                                        + slice9.center_x as f32
-                                        + slice9.center_width as f32,
-                                    rect.max.x,
+                            // Keys without ninepatch data stretch the whole slice as the center.
+                            let center = match &key.slice9 {
+                                Some(slice9) => Rect::new(
+                                    slice9.center_x as f32,
+                                    slice9.center_y as f32,
+                                    slice9.center_x as f32 + slice9.center_width as f32,
+                                    slice9.center_y as f32 + slice9.center_height as f32,
                                 ),
+                                None => Rect::from_corners(Vec2::ZERO, rect.size()),
+                            };
+                            let (x1, x2) = match ninepatch % 3 {
+                                0 => (rect.min.x, rect.min.x + center.min.x),
+                                1 => (rect.min.x + center.min.x, rect.min.x + center.max.x),
+                                2 => (rect.min.x + center.max.x, rect.max.x),
                                 _ => unreachable!(),
                             };
                             let (y1, y2) = match ninepatch / 3 {
-                                0 => (rect.min.y, rect.min.y + slice9.center_y as f32),
-                                1 => (
-                                    rect.min.y + slice9.center_y as f32,
-                                    rect.min.y
-                                        + slice9.center_y as f32
-                                        + slice9.center_height as f32,
-                                ),
-                                2 => (
-                                    rect.min.y
-                                        + slice9.center_y as f32
-                                        + slice9.center_height as f32,
-                                    rect.max.y,
-                                ),
+                                0 => (rect.min.y, rect.min.y + center.min.y),
+                                1 => (rect.min.y + center.min.y, rect.min.y + center.max.y),
+                                2 => (rect.min.y + center.max.y, rect.max.y),
                                 _ => unreachable!(),
                             };
                             let slice_rect = Rect::new(x1, y1, x2, y2);
@@ -351,7 +537,140 @@ impl AssetLoader for AsepriteLoader {
                 }
             }
 
+            let alpha_masks = atlas
+                .textures
+                .iter()
+                .map(|rect| {
+                    AlphaMask::from_image(
+                        &image::imageops::crop_imm(
+                            &buffer,
+                            rect.min.x as u32,
+                            rect.min.y as u32,
+                            rect.width() as u32,
+                            rect.height() as u32,
+                        ),
+                        settings.alpha_threshold,
+                    )
+                })
+                .collect();
+            atlas.texture = load_context.set_labeled_asset(
+                "texture",
+                LoadedAsset::new(Image::new(
+                    Extent3d {
+                        width: buffer.width(),
+                        height: buffer.height(),
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    buffer.into_raw(),
+                    TextureFormat::Rgba8UnormSrgb,
+                )),
+            );
             let atlas = load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas));
+
+            let tile_counts = asefile
+                .tilesets()
+                .iter()
+                .map(|(id, tileset)| (id.value(), tileset.tile_count()))
+                .collect();
+            let mut tile_user_data = read_tile_user_data(bytes, &tile_counts);
+            let tilesets: Vec<_> = asefile
+                .tilesets()
+                .iter()
+                .map(|(id, tileset)| -> anyhow::Result<AsepriteTileset> {
+                    let tile_size = UVec2::new(
+                        tileset.tile_size().width() as u32,
+                        tileset.tile_size().height() as u32,
+                    );
+                    let tile_count = tileset.tile_count();
+                    let num_rows = (tile_count as f32).sqrt() as u32 + 1;
+                    let mut buffer = image::RgbaImage::new(
+                        num_rows * (tile_size.x + padding.x as u32),
+                        num_rows * (tile_size.y + padding.y as u32),
+                    );
+                    for tile in 0..tile_count {
+                        buffer.copy_from(
+                            &tileset.tile_image(tile),
+                            tile % num_rows * (tile_size.x + padding.x as u32),
+                            tile / num_rows * (tile_size.y + padding.y as u32),
+                        )?;
+                    }
+                    let texture = load_context.set_labeled_asset(
+                        &format!("tileset{}/texture", id.value()),
+                        LoadedAsset::new(Image::new(
+                            Extent3d {
+                                width: buffer.width(),
+                                height: buffer.height(),
+                                depth_or_array_layers: 1,
+                            },
+                            TextureDimension::D2,
+                            buffer.into_raw(),
+                            TextureFormat::Rgba8UnormSrgb,
+                        )),
+                    );
+                    let atlas = TextureAtlas::from_grid(
+                        texture,
+                        tile_size.as_vec2(),
+                        num_rows as usize,
+                        num_rows as usize,
+                        Some(padding),
+                        None,
+                    );
+                    Ok(AsepriteTileset {
+                        id: id.value(),
+                        name: tileset.name().to_owned(),
+                        tile_size,
+                        tile_count,
+                        tile_user_data: tile_user_data.remove(&id.value()).unwrap_or_default(),
+                        atlas: load_context.set_labeled_asset(
+                            &format!("tileset{}", id.value()),
+                            LoadedAsset::new(atlas),
+                        ),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            let tilemaps: Vec<_> = asefile
+                .layers()
+                .filter_map(|layer| match layer.layer_type() {
+                    LayerType::Tilemap(tileset) => Some((layer.id(), tileset.value())),
+                    _ => None,
+                })
+                .map(|(layer, tileset)| {
+                    let size = (0..num_frames)
+                        .filter_map(|frame| asefile.tilemap(layer, frame))
+                        .fold(UVec2::ZERO, |size, tilemap| {
+                            size.max(UVec2::new(tilemap.width(), tilemap.height()))
+                        });
+                    let frames = (0..num_frames)
+                        .map(|frame| {
+                            let tilemap = asefile.tilemap(layer, frame);
+                            (0..size.x * size.y)
+                                .map(|i| match &tilemap {
+                                    Some(tilemap)
+                                        if i % size.x < tilemap.width()
+                                            && i / size.x < tilemap.height() =>
+                                    {
+                                        let tile = tilemap.tile(i % size.x, i / size.x);
+                                        TilemapTile {
+                                            id: tile.id().value(),
+                                            flip_x: tile.flip_x(),
+                                            flip_y: tile.flip_y(),
+                                            rotate_90cw: tile.rotate_90cw(),
+                                        }
+                                    }
+                                    _ => TilemapTile::default(),
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    AsepriteTilemap {
+                        layer,
+                        tileset,
+                        size,
+                        frames,
+                    }
+                })
+                .collect();
             let mut layers: Vec<_> = asefile
                 .layers()
                 .map(|layer| layer.name().to_owned())
@@ -362,6 +681,16 @@ impl AssetLoader for AsepriteLoader {
                         layers[parent.id() as usize].clone() + "::" + &layers[layer.id() as usize];
                 }
             }
+            let layer_blend_modes = asefile.layers().map(|layer| layer.blend_mode()).collect();
+            let layer_opacities = asefile.layers().map(|layer| layer.opacity()).collect();
+            let layer_parents = asefile
+                .layers()
+                .map(|layer| layer.parent().map(|parent| parent.id()))
+                .collect();
+            let layer_in_frames = asefile
+                .layers()
+                .map(|layer| settings.hidden_layers_in_frames || !settings.is_hidden(&layer))
+                .collect();
             let tags = (0..asefile.num_tags())
                 .map(|i| asefile.tag(i).clone())
                 .collect();
@@ -370,12 +699,20 @@ impl AssetLoader for AsepriteLoader {
                 .iter()
                 .map(|slice| Slice {
                     name: slice.name.clone(),
+                    user_data: slice
+                        .user_data
+                        .as_ref()
+                        .and_then(|user_data| user_data.text.clone()),
                     segments: slice
                         .keys
                         .iter()
                         .map(|key| SliceSegment {
                             from_frame: key.from_frame as usize,
+                            origin: Vec2::new(key.origin.0 as f32, key.origin.1 as f32),
                             size: Vec2::new(key.size.0 as f32, key.size.1 as f32),
+                            pivot: key
+                                .pivot
+                                .map(|pivot| Vec2::new(pivot.0 as f32, pivot.1 as f32)),
                             ninepatch_center: key.slice9.as_ref().map(|slice9| {
                                 Rect::new(
                                     slice9.center_x as f32,
@@ -389,10 +726,34 @@ impl AssetLoader for AsepriteLoader {
                 })
                 .collect();
 
+            let outlines = atlas_indexes
+                .iter()
+                .filter(|(key, _)| {
+                    let layer = key.layer.map(|layer| &layers[layer as usize]);
+                    let slice = key
+                        .slice
+                        .map(|slice| &asefile.slices()[slice as usize].name);
+                    key.ninepatch.is_none()
+                        && (layer.map_or(false, |name| settings.outline_layers.contains(name))
+                            || slice.map_or(false, |name| settings.outline_slices.contains(name)))
+                })
+                .map(|(_, &index)| {
+                    (
+                        index,
+                        trace_outlines(&alpha_masks[index as usize], settings.outline_tolerance),
+                    )
+                })
+                .collect();
+
             let aseprite = Aseprite {
                 path: load_context.path().to_path_buf(),
+                size: Vec2::new(asefile.width() as f32, asefile.height() as f32),
                 atlas,
                 layers,
+                layer_blend_modes,
+                layer_opacities,
+                layer_parents,
+                layer_in_frames,
                 tags,
                 slices,
                 num_frames,
@@ -400,6 +761,11 @@ impl AssetLoader for AsepriteLoader {
                     .map(|frame| Duration::from_millis(asefile.frame(frame).duration() as u64))
                     .collect(),
                 atlas_indexes,
+                alpha_masks,
+                alpha_threshold: settings.alpha_threshold,
+                outlines,
+                tilesets,
+                tilemaps,
             };
             load_context.set_default_asset(LoadedAsset::new(aseprite));
             Ok(())
@@ -410,3 +776,161 @@ impl AssetLoader for AsepriteLoader {
         &["ase", "aseprite"]
     }
 }
+
+// Composites the descendants of `parent` (or every layer) bottom to top, honoring each layer's
+// blend mode and opacity. `Cel::image` already has the cel opacity baked in. Layers rejected by
+// `include` are skipped along with their children.
+pub(crate) fn compose_layers(
+    asefile: &AsepriteFile,
+    parent: Option<u32>,
+    frame: u32,
+    include: &dyn Fn(&Layer) -> bool,
+) -> image::RgbaImage {
+    let mut image = image::RgbaImage::new(asefile.width() as u32, asefile.height() as u32);
+    draw_layers(asefile, parent, frame, include, &mut image);
+    image
+}
+
+// Like Aseprite, groups are not rendered in isolation: their own blend mode and opacity are
+// ignored and their children blend straight onto the layers beneath the group.
+fn draw_layers(
+    asefile: &AsepriteFile,
+    parent: Option<u32>,
+    frame: u32,
+    include: &dyn Fn(&Layer) -> bool,
+    image: &mut image::RgbaImage,
+) {
+    for layer in asefile
+        .layers()
+        .filter(|layer| layer.parent().map(|parent| parent.id()) == parent)
+        .filter(|layer| include(layer))
+    {
+        match layer.layer_type() {
+            LayerType::Group => draw_layers(asefile, Some(layer.id()), frame, include, image),
+            _ => {
+                let cel = asefile.cel(frame, layer.id());
+                if !cel.is_empty() {
+                    blend_image(image, &cel.image(), layer.blend_mode(), layer.opacity());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::AsepriteAnimation;
+    use crate::test_utils::{asset_app, empty_aseprite, load_aseprite, read_asefile};
+
+    fn assert_matches_flattened(asefile: &AsepriteFile, frame: u32, context: &str) {
+        let composed = compose_layers(asefile, None, frame, &|_| true);
+        let flattened = asefile.frame(frame).image();
+        assert_eq!(composed.dimensions(), flattened.dimensions());
+        for (x, y, pixel) in composed.enumerate_pixels() {
+            let expected = flattened.get_pixel(x, y);
+            // Color is meaningless where both are fully transparent, and the float blend math
+            // may round a channel differently by one.
+            let matches = (pixel[3] == 0 && expected[3] == 0)
+                || pixel
+                    .0
+                    .iter()
+                    .zip(expected.0.iter())
+                    .all(|(a, b)| a.abs_diff(*b) <= 1);
+            assert!(
+                matches,
+                "{} at ({}, {}): {:?}, expected {:?}",
+                context, x, y, pixel.0, expected.0
+            );
+        }
+    }
+
+    #[test]
+    fn compose_layers_matches_flattened_frames() {
+        // Frame `i` has a base layer under a layer using blend mode `i`, with layer opacities of
+        // 255, 160 and 90 and cel opacities of 255 and 180 cycling through the frames.
+        let asefile = read_asefile("tests/blend_modes.aseprite");
+        for frame in 0..asefile.num_frames() {
+            let blend_mode = asefile.layer(frame + 1).blend_mode();
+            assert_matches_flattened(&asefile, frame, &format!("{:?}", blend_mode));
+        }
+    }
+
+    #[test]
+    fn compose_layers_matches_flattened_groups() {
+        // Multiply, screen and difference layers inside a multiply group and a nested overlay
+        // group, both with their own opacity, between a base layer and a translucent top one.
+        let asefile = read_asefile("tests/group_blend.aseprite");
+        for frame in 0..asefile.num_frames() {
+            assert_matches_flattened(&asefile, frame, &format!("frame {}", frame));
+        }
+    }
+
+    #[test]
+    fn slice_pivot_skips_missing_slices_and_empty_keys() {
+        let mut aseprite = empty_aseprite(3);
+        aseprite.slices.push(Slice {
+            name: "Feet".to_owned(),
+            user_data: None,
+            segments: vec![
+                SliceSegment {
+                    from_frame: 0,
+                    origin: Vec2::new(2.0, 3.0),
+                    size: Vec2::new(4.0, 4.0),
+                    pivot: Some(Vec2::new(1.0, 2.0)),
+                    ninepatch_center: None,
+                },
+                SliceSegment {
+                    from_frame: 2,
+                    ..Default::default()
+                },
+            ],
+        });
+
+        assert_eq!(aseprite.slice_pivot("Feet", 1), Some(Vec2::new(3.0, 5.0)));
+        assert_eq!(aseprite.slice_pivot("Feet", 2), None);
+        assert_eq!(aseprite.slice_pivot("Head", 0), None);
+    }
+
+    #[test]
+    fn layer_ranges_cover_empty_cels() {
+        // `Mode1` only has a cel on frame 1, so its first and last frames are empty.
+        let mut app = asset_app();
+        let handle = load_aseprite(&mut app, "tests/blend_modes.aseprite");
+        let aseprite = app
+            .world
+            .resource::<Assets<Aseprite>>()
+            .get(&handle)
+            .unwrap();
+        let range = aseprite
+            .atlas_range(Some("Mode1"), None, None, None)
+            .unwrap();
+        assert_eq!(range.len(), aseprite.num_frames as usize);
+        for frame in 0..aseprite.num_frames {
+            let index = aseprite.atlas_indexes[&AtlasKey {
+                layer: Some(aseprite.layer_id("Mode1")),
+                frame,
+                slice: None,
+                ninepatch: None,
+            }];
+            assert_eq!(index, range.start + frame);
+            let mask = &aseprite.alpha_masks[index as usize];
+            let opaque =
+                (0..mask.height() as i32).any(|y| (0..mask.width() as i32).any(|x| mask.get(x, y)));
+            assert_eq!(opaque, frame == 1, "frame {}", frame);
+        }
+
+        let mut animation = AsepriteAnimation::default();
+        let atlas = AsepriteAtlas {
+            layer: Some("Mode1"),
+            ..Default::default()
+        };
+        assert_eq!(animation.fixup(&atlas, aseprite), range.start as usize);
+        animation.step(Duration::from_millis(100), aseprite);
+        assert_eq!(
+            animation.step(Duration::ZERO, aseprite),
+            range.start as usize + 1
+        );
+        assert_eq!(animation.current_frame(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+use asefile::BlendMode;
+use image::{Rgba, RgbaImage};
+
+// Port of Aseprite's `doc/blend_funcs.cpp`: the blend function mixes the source color with the
+// backdrop, then the result is composited over the backdrop with normal alpha blending.
+
+pub(crate) fn blend_image(backdrop: &mut RgbaImage, src: &RgbaImage, mode: BlendMode, opacity: u8) {
+    for (b, s) in backdrop.pixels_mut().zip(src.pixels()) {
+        *b = blend(*b, *s, mode, opacity);
+    }
+}
+
+pub(crate) fn blend(backdrop: Rgba<u8>, src: Rgba<u8>, mode: BlendMode, opacity: u8) -> Rgba<u8> {
+    if src[3] == 0 || opacity == 0 {
+        return backdrop;
+    }
+    let b = to_unit(backdrop);
+    let s = to_unit(src);
+    let mixed = match mode {
+        BlendMode::Normal => [s[0], s[1], s[2]],
+        BlendMode::Multiply => separable(b, s, |b, s| b * s),
+        BlendMode::Screen => separable(b, s, screen),
+        BlendMode::Overlay => separable(b, s, |b, s| hard_light(s, b)),
+        BlendMode::Darken => separable(b, s, f32::min),
+        BlendMode::Lighten => separable(b, s, f32::max),
+        BlendMode::ColorDodge => separable(b, s, |b, s| {
+            if b == 0.0 {
+                0.0
+            } else if b >= 1.0 - s {
+                1.0
+            } else {
+                b / (1.0 - s)
+            }
+        }),
+        BlendMode::ColorBurn => separable(b, s, |b, s| {
+            if b == 1.0 {
+                1.0
+            } else if 1.0 - b >= s {
+                0.0
+            } else {
+                1.0 - (1.0 - b) / s
+            }
+        }),
+        BlendMode::HardLight => separable(b, s, hard_light),
+        BlendMode::SoftLight => separable(b, s, |b, s| {
+            let d = if b <= 0.25 {
+                ((16.0 * b - 12.0) * b + 4.0) * b
+            } else {
+                b.sqrt()
+            };
+            if s <= 0.5 {
+                b - (1.0 - 2.0 * s) * b * (1.0 - b)
+            } else {
+                b + (2.0 * s - 1.0) * (d - b)
+            }
+        }),
+        BlendMode::Difference => separable(b, s, |b, s| (b - s).abs()),
+        BlendMode::Exclusion => separable(b, s, |b, s| b + s - 2.0 * b * s),
+        BlendMode::Hue => set_lum(set_sat(rgb(s), sat(rgb(b))), lum(rgb(b))),
+        BlendMode::Saturation => set_lum(set_sat(rgb(b), sat(rgb(s))), lum(rgb(b))),
+        BlendMode::Color => set_lum(rgb(s), lum(rgb(b))),
+        BlendMode::Luminosity => set_lum(rgb(b), lum(rgb(s))),
+        BlendMode::Addition => separable(b, s, |b, s| (b + s).min(1.0)),
+        BlendMode::Subtract => separable(b, s, |b, s| (b - s).max(0.0)),
+        BlendMode::Divide => separable(b, s, |b, s| {
+            if b == 0.0 {
+                0.0
+            } else if b >= s {
+                1.0
+            } else {
+                b / s
+            }
+        }),
+    };
+    normal(
+        b,
+        [mixed[0], mixed[1], mixed[2], s[3]],
+        opacity as f32 / 255.0,
+    )
+}
+
+fn normal(b: [f32; 4], s: [f32; 4], opacity: f32) -> Rgba<u8> {
+    if b[3] == 0.0 {
+        return from_unit([s[0], s[1], s[2], s[3] * opacity]);
+    }
+    let sa = s[3] * opacity;
+    let ra = sa + b[3] - b[3] * sa;
+    let channel = |i: usize| b[i] + (s[i] - b[i]) * sa / ra;
+    from_unit([channel(0), channel(1), channel(2), ra])
+}
+
+fn screen(b: f32, s: f32) -> f32 {
+    b + s - b * s
+}
+
+fn hard_light(b: f32, s: f32) -> f32 {
+    if s < 0.5 {
+        b * (2.0 * s)
+    } else {
+        screen(b, 2.0 * s - 1.0)
+    }
+}
+
+fn separable(b: [f32; 4], s: [f32; 4], f: impl Fn(f32, f32) -> f32) -> [f32; 3] {
+    [f(b[0], s[0]), f(b[1], s[1]), f(b[2], s[2])]
+}
+
+fn rgb(c: [f32; 4]) -> [f32; 3] {
+    [c[0], c[1], c[2]]
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn clip_color(c: [f32; 3]) -> [f32; 3] {
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    c.map(|v| {
+        let v = if n < 0.0 {
+            l + (v - l) * l / (l - n)
+        } else {
+            v
+        };
+        if x > 1.0 {
+            l + (v - l) * (1.0 - l) / (x - l)
+        } else {
+            v
+        }
+    })
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    clip_color(c.map(|v| v + d))
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+    if max == min {
+        return [0.0; 3];
+    }
+    c.map(|v| {
+        if v == max {
+            s
+        } else if v == min {
+            0.0
+        } else {
+            (v - min) * s / (max - min)
+        }
+    })
+}
+
+fn to_unit(c: Rgba<u8>) -> [f32; 4] {
+    c.0.map(|v| v as f32 / 255.0)
+}
+
+fn from_unit(c: [f32; 4]) -> Rgba<u8> {
+    Rgba(c.map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8))
+}
@@ -1,5 +1,8 @@
 use crate::assets::Aseprite;
+use crate::ninepatch::NinepatchFill;
 use bevy::prelude::*;
+use bevy::sprite::Mesh2dHandle;
+use std::collections::HashMap;
 use std::ops::Range;
 use std::time::Duration;
 
@@ -36,34 +39,54 @@ pub struct AsepriteAnimation {
     pub time_elapsed: Duration,
     pub current_index: u32,
     pub index_range: Range<u32>,
+    pub frame_range: Range<u32>,
     pub pong: bool,
 }
 
 impl AsepriteAnimation {
     pub fn fixup(&mut self, ase_atlas: &AsepriteAtlas, aseprite: &Aseprite) -> usize {
-        let atlas_range = aseprite.atlas_range(
+        match aseprite.atlas_range(
             ase_atlas.layer,
             ase_atlas.tag,
             ase_atlas.slice,
             ase_atlas.ninepatch,
-        );
-        self.direction = ase_atlas
-            .tag
+        ) {
+            Some(atlas_range) => self.reset(atlas_range, ase_atlas.tag, aseprite),
+            None => error!(
+                "No atlas entries for {:?} at `{}`",
+                ase_atlas,
+                aseprite.path.display()
+            ),
+        }
+        self.current_index as usize
+    }
+
+    /// Like `fixup`, but steps through frame numbers instead of atlas indexes.
+    pub fn fixup_frames(&mut self, tag: Option<&str>, aseprite: &Aseprite) -> u32 {
+        self.reset(aseprite.frame_range(tag), tag, aseprite);
+        self.current_index
+    }
+
+    fn reset(&mut self, index_range: Range<u32>, tag: Option<&str>, aseprite: &Aseprite) {
+        self.direction = tag
             .map(|name| aseprite.tag(name).animation_direction().into())
             .unwrap_or_default();
         self.current_index = match self.direction {
-            AnimationDirection::Forward | AnimationDirection::PingPong => atlas_range.start,
-            AnimationDirection::Backward => atlas_range.end - 1,
+            AnimationDirection::Forward | AnimationDirection::PingPong => index_range.start,
+            AnimationDirection::Backward => index_range.end - 1,
         };
-        self.index_range = atlas_range;
+        self.index_range = index_range;
+        self.frame_range = aseprite.frame_range(tag);
         self.time_elapsed = Duration::from_millis(0);
-        self.current_index as usize
+    }
+
+    pub fn current_frame(&self) -> u32 {
+        self.frame_range.start + (self.current_index - self.index_range.start)
     }
 
     pub fn step(&mut self, elapsed: Duration, aseprite: &Aseprite) -> usize {
         let mut current_index = self.current_index;
-        let mut current_frame_duration =
-            aseprite.frame_duration((self.current_index - self.index_range.start) as usize);
+        let mut current_frame_duration = aseprite.frame_duration(self.current_frame() as usize);
         while self.time_elapsed >= current_frame_duration {
             self.time_elapsed -= current_frame_duration;
             current_index = match self.direction {
@@ -97,8 +120,9 @@ impl AsepriteAnimation {
                     }
                 }
             };
-            current_frame_duration =
-                aseprite.frame_duration((current_index - self.index_range.start) as usize);
+            current_frame_duration = aseprite.frame_duration(
+                (self.frame_range.start + (current_index - self.index_range.start)) as usize,
+            );
         }
 
         self.current_index = current_index;
@@ -108,11 +132,84 @@ impl AsepriteAnimation {
     }
 }
 
+/// Anchors the sprite at the pivot of the named slice on the current frame, so the entity's
+/// translation is the authored ground point.
+#[derive(Component, Clone, Default, Eq, PartialEq, Debug)]
+pub struct AsepritePivot {
+    pub slice: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AsepriteHitbox {
+    pub slice: &'static str,
+    pub local: Rect,
+    pub world: Rect,
+}
+
+/// Rects of the listed slices on the current frame. When `user_data` is set, only slices whose
+/// user data text matches it are kept.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteHitboxes {
+    pub slices: Vec<&'static str>,
+    pub user_data: Option<&'static str>,
+    pub hitboxes: Vec<AsepriteHitbox>,
+}
+
+/// Picks which of an entity's `AsepriteHitboxes` deal (`hit`) and receive (`hurt`) hits. A hit
+/// box overlapping another entity's hurt box is reported when `mask` shares a bit with the other
+/// entity's `layers`. Requires `AsepriteHitboxPlugin`.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteHitboxLayers {
+    pub hit: Vec<&'static str>,
+    pub hurt: Vec<&'static str>,
+    pub layers: u32,
+    pub mask: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct AsepriteHitOverlap {
+    pub hitter: Entity,
+    pub hit_slice: &'static str,
+    pub target: Entity,
+    pub hurt_slice: &'static str,
+}
+
+#[derive(Event, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AsepriteHitEvent {
+    Started(AsepriteHitOverlap),
+    Ended(AsepriteHitOverlap),
+}
+
+/// Keeps a child entity on a slice of its parent's sprite for the parent's current frame: the
+/// slice pivot when `pivot` is set and the key has one, otherwise the slice center. With
+/// `mirror`, the child's scale is flipped along with the parent sprite.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteSocket {
+    pub slice: &'static str,
+    pub pivot: bool,
+    pub mirror: bool,
+}
+
+/// Opt-in root motion. The sprite is anchored at the pivot of `slice` (or its center when the key
+/// has no pivot) so the art stays put, and the pivot's movement since the previous frame is
+/// reported in `delta`, in the entity's `Transform` space. With `apply`, `delta` is also added to
+/// the `Transform`. Switching to another tag starts over without motion.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteRootMotion {
+    pub slice: &'static str,
+    pub apply: bool,
+    pub delta: Vec2,
+    pub last_frame: Option<u32>,
+    pub last_pivot: Vec2,
+    pub last_frame_range: Range<u32>,
+}
+
 #[derive(Bundle, Default)]
 pub struct AsepriteBundle {
     pub aseprite: Handle<Aseprite>,
     pub aseprite_atlas: AsepriteAtlas,
     pub aseprite_animation: AsepriteAnimation,
+    pub pivot: AsepritePivot,
 
     pub sprite: TextureAtlasSprite,
     pub texture_atlas: Handle<TextureAtlas>,
@@ -122,5 +219,137 @@ pub struct AsepriteBundle {
     pub computed_visibility: ComputedVisibility,
 }
 
+/// A world-space ninepatch stretched to `size`, built from the ninepatch slice selected by
+/// `AsepriteAtlas::slice` and drawn as a single mesh.
+#[derive(Component, Clone, Default, Debug, PartialEq)]
+pub struct AsepriteNinepatch {
+    pub size: Vec2,
+    pub fill: NinepatchFill,
+}
+
+/// The frame the ninepatch mesh was last built for.
 #[derive(Component, Default)]
-pub struct AsepriteNinepatch;
+pub struct AsepriteNinepatchMesh {
+    pub frame: Option<u32>,
+}
+
+#[derive(Bundle, Default)]
+pub struct AsepriteNinepatchBundle {
+    pub aseprite: Handle<Aseprite>,
+    pub aseprite_atlas: AsepriteAtlas,
+    pub aseprite_animation: AsepriteAnimation,
+    pub ninepatch: AsepriteNinepatch,
+    pub ninepatch_mesh: AsepriteNinepatchMesh,
+
+    pub mesh: Mesh2dHandle,
+    pub material: Handle<ColorMaterial>,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub computed_visibility: ComputedVisibility,
+}
+
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct AsepriteLayer {
+    pub layer: &'static str,
+    pub tag: Option<&'static str>,
+}
+
+/// Plays each listed layer (or layer group) as its own animated child sprite,
+/// stacked by layer index under the entity's transform.
+#[derive(Component, Clone, Default, Eq, PartialEq, Debug)]
+pub struct AsepriteLayers {
+    pub layers: Vec<AsepriteLayer>,
+}
+
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct AsepriteLayersChildren(pub Vec<Entity>);
+
+#[derive(Bundle, Default)]
+pub struct AsepriteLayersBundle {
+    pub aseprite: Handle<Aseprite>,
+    pub aseprite_layers: AsepriteLayers,
+    pub children: AsepriteLayersChildren,
+
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub computed_visibility: ComputedVisibility,
+}
+
+#[derive(Clone, Debug)]
+pub struct AsepriteEquipmentSlot {
+    pub name: &'static str,
+    pub aseprite: Handle<Aseprite>,
+}
+
+/// Attaches equipment sprites to a driver `AsepriteBundle`. Each slot's file must share the
+/// driver's frame layout; slot sprites show the driver's current frame and are stacked in
+/// slot order.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteEquipment {
+    pub slots: Vec<AsepriteEquipmentSlot>,
+}
+
+impl AsepriteEquipment {
+    pub fn with_slot(mut self, name: &'static str, aseprite: Handle<Aseprite>) -> Self {
+        self.set_slot(name, aseprite);
+        self
+    }
+    pub fn slot(&self, name: &str) -> Option<&Handle<Aseprite>> {
+        self.slots
+            .iter()
+            .find(|slot| slot.name == name)
+            .map(|slot| &slot.aseprite)
+    }
+    pub fn set_slot(&mut self, name: &'static str, aseprite: Handle<Aseprite>) {
+        match self.slots.iter_mut().find(|slot| slot.name == name) {
+            Some(slot) => slot.aseprite = aseprite,
+            None => self.slots.push(AsepriteEquipmentSlot { name, aseprite }),
+        }
+    }
+    pub fn remove_slot(&mut self, name: &str) {
+        self.slots.retain(|slot| slot.name != name);
+    }
+}
+
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct AsepriteEquipmentChildren(pub HashMap<&'static str, Entity>);
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AsepriteEquipmentItem {
+    pub slot: &'static str,
+}
+
+#[derive(Bundle, Default)]
+pub struct AsepriteEquipmentBundle {
+    pub equipment: AsepriteEquipment,
+    pub children: AsepriteEquipmentChildren,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::empty_aseprite;
+
+    #[test]
+    fn step_uses_durations_of_absolute_frames() {
+        // A tag over frames 2..5 whose atlas entries start at index 7. Frames 0 and 1 are much
+        // longer, so looking durations up by the tag-relative offset would never advance.
+        let mut aseprite = empty_aseprite(5);
+        aseprite.frame_durations[0] = Duration::from_millis(1000);
+        aseprite.frame_durations[1] = Duration::from_millis(1000);
+        let mut animation = AsepriteAnimation {
+            current_index: 7,
+            index_range: 7..10,
+            frame_range: 2..5,
+            ..Default::default()
+        };
+
+        assert_eq!(animation.step(Duration::from_millis(150), &aseprite), 7);
+        assert_eq!(animation.step(Duration::ZERO, &aseprite), 8);
+        assert_eq!(animation.current_frame(), 3);
+        assert_eq!(animation.step(Duration::from_millis(100), &aseprite), 8);
+        assert_eq!(animation.step(Duration::ZERO, &aseprite), 9);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::assets::{Aseprite, AtlasKey};
+use crate::blend::blend_image;
+use crate::mask::AlphaMask;
+use crate::utils::coalesce;
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use image::{GenericImage, ImageBuffer, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+/// Cache of CPU-baked atlases that show only a chosen set of layers.
+///
+/// The returned `Handle<Aseprite>` carries the source's tags and frame timings, so it can be
+/// used in an `AsepriteBundle` with `AsepriteAtlas { tag, .. }`. Slices are kept for pivots,
+/// hitboxes and sockets, but only full frames are baked: layer and slice atlases are not
+/// available on composites.
+#[derive(Resource, Default)]
+pub struct AsepriteLayerComposites {
+    cache: HashMap<(HandleId, Vec<u32>), Handle<Aseprite>>,
+}
+
+impl AsepriteLayerComposites {
+    /// Layers named in `visible_layers` are drawn even when they are hidden in the file, while
+    /// layers picked through a listed group follow the file's visibility.
+    pub fn compose(
+        &mut self,
+        aseprite_handle: &Handle<Aseprite>,
+        visible_layers: &[&str],
+        aseprites: &mut Assets<Aseprite>,
+        atlases: &mut Assets<TextureAtlas>,
+        images: &mut Assets<Image>,
+    ) -> Option<Handle<Aseprite>> {
+        let source = aseprites.get(aseprite_handle)?;
+        let mut layers: Vec<u32> = source
+            .layers
+            .iter()
+            .enumerate()
+            .filter(|(id, name)| {
+                visible_layers.iter().any(|visible| {
+                    name.as_str() == *visible
+                        || (name.starts_with(&format!("{}::", visible))
+                            && shown_below(source, *id as u32, visible))
+                })
+            })
+            .filter(|(_, name)| {
+                let prefix = format!("{}::", name);
+                !source.layers.iter().any(|other| other.starts_with(&prefix))
+            })
+            .map(|(id, _)| id as u32)
+            .collect();
+        layers.sort_unstable();
+        let key = (aseprite_handle.id(), layers);
+        if let Some(handle) = self.cache.get(&key) {
+            if aseprites.contains(handle) {
+                return Some(handle.clone());
+            }
+        }
+
+        let source_atlas = atlases.get(&source.atlas)?;
+        let source_image = images.get(&source_atlas.texture)?;
+        let source_buffer = ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(
+            source_image.texture_descriptor.size.width,
+            source_image.texture_descriptor.size.height,
+            &source_image.data[..],
+        )?;
+        let canvas = source_atlas.textures[source.frame_index(0)? as usize];
+        let padding = Vec2::new(1.0, 1.0);
+        let cel_width = canvas.width() as u32 + padding.x as u32;
+        let cel_height = canvas.height() as u32 + padding.y as u32;
+        let num_rows = (source.num_frames as f32).sqrt() as u32 + 1;
+        let mut buffer = RgbaImage::new(num_rows * cel_width, num_rows * cel_height);
+
+        for frame in 0..source.num_frames {
+            let mut frame_image = RgbaImage::new(canvas.width() as u32, canvas.height() as u32);
+            draw_selected(
+                source,
+                source_atlas,
+                &source_buffer,
+                None,
+                frame,
+                &key.1,
+                &mut frame_image,
+            );
+            buffer
+                .copy_from(
+                    &frame_image,
+                    frame % num_rows * cel_width,
+                    frame / num_rows * cel_height,
+                )
+                .ok()?;
+        }
+
+        let alpha_masks = (0..source.num_frames)
+            .map(|frame| {
+                AlphaMask::from_image(
+                    &image::imageops::crop_imm(
+                        &buffer,
+                        frame % num_rows * cel_width,
+                        frame / num_rows * cel_height,
+                        canvas.width() as u32,
+                        canvas.height() as u32,
+                    ),
+                    source.alpha_threshold,
+                )
+            })
+            .collect();
+        let texture = images.add(Image::new(
+            Extent3d {
+                width: buffer.width(),
+                height: buffer.height(),
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            buffer.into_raw(),
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        let atlas = atlases.add(TextureAtlas::from_grid(
+            texture,
+            canvas.size(),
+            num_rows as usize,
+            num_rows as usize,
+            Some(padding),
+            None,
+        ));
+        let composite = Aseprite {
+            path: source.path.clone(),
+            size: source.size,
+            layers: source.layers.clone(),
+            layer_blend_modes: source.layer_blend_modes.clone(),
+            layer_opacities: source.layer_opacities.clone(),
+            layer_parents: source.layer_parents.clone(),
+            layer_in_frames: source.layer_in_frames.clone(),
+            tags: source.tags.clone(),
+            slices: source.slices.clone(),
+            frame_durations: source.frame_durations.clone(),
+            num_frames: source.num_frames,
+            atlas_indexes: HashMap::from_iter((0..source.num_frames).map(|frame| {
+                (
+                    AtlasKey {
+                        layer: None,
+                        frame,
+                        slice: None,
+                        ninepatch: None,
+                    },
+                    frame,
+                )
+            })),
+            atlas,
+            alpha_masks,
+            alpha_threshold: source.alpha_threshold,
+            outlines: HashMap::new(),
+            tilesets: Vec::new(),
+            tilemaps: Vec::new(),
+        };
+        let handle = aseprites.add(composite);
+        self.cache.insert(key, handle.clone());
+        Some(handle)
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+// Whether `layer` and its ancestors below the group named `group` are baked into full frames.
+fn shown_below(source: &Aseprite, mut layer: u32, group: &str) -> bool {
+    while source.layers[layer as usize] != group {
+        if !source.layer_in_frames[layer as usize] {
+            return false;
+        }
+        layer = coalesce!(source.layer_parents[layer as usize], return false);
+    }
+    true
+}
+
+// Walks the layer tree like the loader does for full frames, but only draws the leaf layers in
+// `selected`, whether or not they are hidden in the file. Groups are not isolated, so their
+// children blend onto the layers beneath them.
+fn draw_selected(
+    source: &Aseprite,
+    source_atlas: &TextureAtlas,
+    source_buffer: &ImageBuffer<Rgba<u8>, &[u8]>,
+    parent: Option<u32>,
+    frame: u32,
+    selected: &[u32],
+    composed: &mut RgbaImage,
+) {
+    for layer in 0..source.layers.len() as u32 {
+        if source.layer_parents[layer as usize] != parent {
+            continue;
+        }
+        if source.layer_parents.contains(&Some(layer)) {
+            draw_selected(
+                source,
+                source_atlas,
+                source_buffer,
+                Some(layer),
+                frame,
+                selected,
+                composed,
+            );
+            continue;
+        }
+        if !selected.contains(&layer) {
+            continue;
+        }
+        let index = coalesce!(
+            source.atlas_indexes.get(&AtlasKey {
+                layer: Some(layer),
+                frame,
+                slice: None,
+                ninepatch: None,
+            }),
+            continue
+        );
+        let rect = source_atlas.textures[*index as usize];
+        let layer_image = image::imageops::crop_imm(
+            source_buffer,
+            rect.min.x as u32,
+            rect.min.y as u32,
+            rect.width() as u32,
+            rect.height() as u32,
+        )
+        .to_image();
+        blend_image(
+            composed,
+            &layer_image,
+            source.layer_blend_modes[layer as usize],
+            source.layer_opacities[layer as usize],
+        );
+    }
+}
+
+pub fn invalidate_aseprite_layer_composites(
+    mut composites: ResMut<AsepriteLayerComposites>,
+    mut ev_asset: EventReader<AssetEvent<Aseprite>>,
+) {
+    for ev in ev_asset.iter() {
+        if let AssetEvent::Modified { handle } | AssetEvent::Removed { handle } = ev {
+            composites
+                .cache
+                .retain(|(source, _), _| *source != handle.id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{compose_layers, AsepriteLoaderSettings};
+    use crate::test_utils::{asset_app, frame_image, load_aseprite, read_asefile};
+
+    fn compose(app: &mut App, handle: &Handle<Aseprite>, visible: &[&str]) -> Handle<Aseprite> {
+        let mut composites = AsepriteLayerComposites::default();
+        app.world
+            .resource_scope(|world, mut aseprites: Mut<Assets<Aseprite>>| {
+                world.resource_scope(|world, mut atlases: Mut<Assets<TextureAtlas>>| {
+                    let mut images = world.resource_mut::<Assets<Image>>();
+                    composites.compose(handle, visible, &mut aseprites, &mut atlases, &mut images)
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn composite_of_shown_layers_matches_frames() {
+        // Nested groups with their own blend modes and opacity, plus an invisible and a reference
+        // layer that the loader leaves out of frames by default. Listing the root layers that
+        // frames include picks their shown children through the groups.
+        let mut app = asset_app();
+        let handle = load_aseprite(&mut app, "tests/layer_tree.aseprite");
+        let source = app
+            .world
+            .resource::<Assets<Aseprite>>()
+            .get(&handle)
+            .unwrap();
+        let layers: Vec<_> = (0..source.layers.len())
+            .filter(|&layer| source.layer_parents[layer].is_none() && source.layer_in_frames[layer])
+            .map(|layer| source.layers[layer].clone())
+            .collect();
+        assert_eq!(layers, ["Back", "Group", "Front", "_Guide"]);
+        let visible: Vec<&str> = layers.iter().map(String::as_str).collect();
+        let composite = compose(&mut app, &handle, &visible);
+
+        let aseprites = app.world.resource::<Assets<Aseprite>>();
+        let source = aseprites.get(&handle).unwrap();
+        let composite = aseprites.get(&composite).unwrap();
+        for frame in 0..source.num_frames {
+            assert_eq!(
+                frame_image(&app, composite, frame),
+                frame_image(&app, source, frame),
+                "frame {}",
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn listed_hidden_layers_are_drawn() {
+        let mut app = asset_app();
+        let handle = load_aseprite(&mut app, "tests/layer_tree.aseprite");
+        let composite = compose(
+            &mut app,
+            &handle,
+            &[
+                "Back",
+                "Group",
+                "Front",
+                "_Guide",
+                "Group::Hidden",
+                "Reference",
+            ],
+        );
+
+        let settings = AsepriteLoaderSettings::default();
+        let asefile = read_asefile("tests/layer_tree.aseprite");
+        let aseprites = app.world.resource::<Assets<Aseprite>>();
+        let composite = aseprites.get(&composite).unwrap();
+        for frame in 0..composite.num_frames {
+            let expected = compose_layers(&asefile, None, frame, &|layer| {
+                matches!(layer.name(), "Hidden" | "Reference") || !settings.is_hidden(layer)
+            });
+            assert_eq!(
+                frame_image(&app, composite, frame),
+                expected,
+                "frame {}",
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn composites_keep_slices() {
+        let mut app = asset_app();
+        let handle = load_aseprite(&mut app, "ui.aseprite");
+        let composite = compose(&mut app, &handle, &["Layer"]);
+
+        let aseprites = app.world.resource::<Assets<Aseprite>>();
+        let source = aseprites.get(&handle).unwrap();
+        let composite = aseprites.get(&composite).unwrap();
+        assert_eq!(composite.slices.len(), source.slices.len());
+        for slice in source.slices.iter() {
+            let segment = composite.slice(&slice.name, 0);
+            assert_eq!(segment.rect(), source.slice(&slice.name, 0).rect());
+            assert_eq!(
+                segment.ninepatch_center,
+                source.slice(&slice.name, 0).ninepatch_center
+            );
+        }
+    }
+}
@@ -1,11 +1,32 @@
 #![feature(let_chains)]
 
 mod assets;
+mod blend;
 mod components;
+mod composites;
+mod mask;
+mod ninepatch;
+mod outline;
+mod picking;
 mod plugins;
 mod systems;
+#[cfg(test)]
+mod test_utils;
+mod tilemap;
+#[cfg(feature = "ui")]
+mod ui;
+mod user_data;
 mod utils;
 
 pub use assets::*;
 pub use components::*;
+pub use composites::*;
+pub use mask::*;
+pub use ninepatch::*;
+pub use outline::trace_outlines;
+pub use picking::*;
 pub use plugins::*;
+pub use tilemap::*;
+#[cfg(feature = "ui")]
+pub use ui::*;
+pub use user_data::AsepriteTileUserData;
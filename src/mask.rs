@@ -0,0 +1,254 @@
+use crate::assets::Aseprite;
+use crate::systems::local_to_world;
+use bevy::prelude::*;
+use image::{GenericImageView, Rgba};
+
+/// One bit per pixel of an atlas entry, set where the alpha is above the loader's
+/// `alpha_threshold`.
+#[derive(Clone, Debug, Default)]
+pub struct AlphaMask {
+    width: u32,
+    height: u32,
+    bits: Vec<u64>,
+}
+
+impl AlphaMask {
+    pub fn from_image<I: GenericImageView<Pixel = Rgba<u8>>>(image: &I, threshold: u8) -> Self {
+        let (width, height) = image.dimensions();
+        let mut bits = vec![0u64; ((width * height) as usize + 63) / 64];
+        for (x, y, pixel) in image.pixels() {
+            if pixel[3] > threshold {
+                let i = (y * width + x) as usize;
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Self {
+            width,
+            height,
+            bits,
+        }
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+    /// Whether the pixel is opaque; out-of-bounds pixels are transparent.
+    pub fn get(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return false;
+        }
+        let i = (y as u32 * self.width + x as u32) as usize;
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+}
+
+/// A sprite showing an atlas entry of an `Aseprite`, placed in the world.
+#[derive(Clone, Copy)]
+pub struct PosedSprite<'a> {
+    pub aseprite: &'a Aseprite,
+    pub sprite: &'a TextureAtlasSprite,
+    pub transform: &'a GlobalTransform,
+}
+
+impl<'a> PosedSprite<'a> {
+    /// The mask of the sprite's atlas entry, or `None` when the index is out of range.
+    pub fn mask(&self) -> Option<&'a AlphaMask> {
+        self.aseprite.alpha_masks.get(self.sprite.index)
+    }
+    /// The sprite quad in local space, as laid out by `TextureAtlasSprite`.
+    pub fn local_rect(&self) -> Option<Rect> {
+        let size = match self.sprite.custom_size {
+            Some(size) => size,
+            None => self.mask()?.size(),
+        };
+        let min = -(self.sprite.anchor.as_vec() + Vec2::splat(0.5)) * size;
+        Some(Rect::from_corners(min, min + size))
+    }
+    pub fn world_rect(&self) -> Option<Rect> {
+        Some(local_to_world(self.local_rect()?, self.transform))
+    }
+    /// The world-space center of a mask pixel.
+    pub fn pixel_to_world(&self, x: u32, y: u32) -> Option<Vec2> {
+        let mask = self.mask()?;
+        let rect = self.local_rect()?;
+        let mut offset = (Vec2::new(x as f32, y as f32) + 0.5) / mask.size();
+        if self.sprite.flip_x {
+            offset.x = 1.0 - offset.x;
+        }
+        if self.sprite.flip_y {
+            offset.y = 1.0 - offset.y;
+        }
+        let local = Vec2::new(
+            rect.min.x + offset.x * rect.width(),
+            rect.max.y - offset.y * rect.height(),
+        );
+        Some(self.transform.transform_point(local.extend(0.0)).truncate())
+    }
+    /// Whether the sprite has an opaque pixel at a world-space point. Sprites without a mask
+    /// have none.
+    pub fn is_opaque_at(&self, point: Vec2) -> bool {
+        let (Some(mask), Some(rect)) = (self.mask(), self.local_rect()) else {
+            return false;
+        };
+        let local = self
+            .transform
+            .affine()
+            .inverse()
+            .transform_point3(point.extend(0.0))
+            .truncate();
+        let mut offset = Vec2::new(
+            (local.x - rect.min.x) / rect.width(),
+            (rect.max.y - local.y) / rect.height(),
+        );
+        if self.sprite.flip_x {
+            offset.x = 1.0 - offset.x;
+        }
+        if self.sprite.flip_y {
+            offset.y = 1.0 - offset.y;
+        }
+        let pixel = (offset * mask.size()).floor();
+        mask.get(pixel.x as i32, pixel.y as i32)
+    }
+}
+
+fn sample_overlap(a: &PosedSprite, mask: &AlphaMask, b: &PosedSprite, region: Rect) -> bool {
+    (0..mask.height()).any(|y| {
+        (0..mask.width()).any(|x| {
+            if !mask.get(x as i32, y as i32) {
+                return false;
+            }
+            a.pixel_to_world(x, y).map_or(false, |point| {
+                region.contains(point) && b.is_opaque_at(point)
+            })
+        })
+    })
+}
+
+/// Tests two posed sprites for overlapping opaque pixels. Each sprite's pixel centers are
+/// sampled against the other's mask, so scaled and rotated sprites are supported. Returns `None`
+/// when either sprite's index has no mask.
+pub fn pixel_overlap(a: &PosedSprite, b: &PosedSprite) -> Option<bool> {
+    let (mask_a, mask_b) = (a.mask()?, b.mask()?);
+    let region = a.world_rect()?.intersect(b.world_rect()?);
+    if region.is_empty() {
+        return Some(false);
+    }
+    Some(sample_overlap(a, mask_a, b, region) || sample_overlap(b, mask_b, a, region))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::empty_aseprite;
+    use bevy::sprite::Anchor;
+    use image::RgbaImage;
+
+    // A 4x2 mask whose left column is opaque, plus a half-transparent pixel at (2, 0).
+    fn left_column() -> AlphaMask {
+        let mut image = RgbaImage::new(4, 2);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 1, Rgba([255, 0, 0, 255]));
+        image.put_pixel(2, 0, Rgba([255, 0, 0, 128]));
+        AlphaMask::from_image(&image, 128)
+    }
+
+    fn posed<'a>(
+        aseprite: &'a Aseprite,
+        sprite: &'a TextureAtlasSprite,
+        transform: &'a GlobalTransform,
+    ) -> PosedSprite<'a> {
+        PosedSprite {
+            aseprite,
+            sprite,
+            transform,
+        }
+    }
+
+    #[test]
+    fn mask_from_image() {
+        let mask = left_column();
+        assert_eq!((mask.width(), mask.height()), (4, 2));
+        assert!(mask.get(0, 0));
+        assert!(mask.get(0, 1));
+        assert!(!mask.get(1, 0));
+        // At the threshold is transparent.
+        assert!(!mask.get(2, 0));
+        assert!(!mask.get(-1, 0));
+        assert!(!mask.get(0, 2));
+        assert!(!mask.get(4, 0));
+    }
+
+    #[test]
+    fn overlap_follows_flip_anchor_and_scale() {
+        let mut aseprite = empty_aseprite(1);
+        aseprite.alpha_masks = vec![left_column()];
+        let sprite = TextureAtlasSprite::default();
+        let flipped = TextureAtlasSprite {
+            flip_x: true,
+            ..Default::default()
+        };
+        let anchored = TextureAtlasSprite {
+            anchor: Anchor::BottomLeft,
+            ..Default::default()
+        };
+        let origin = GlobalTransform::IDENTITY;
+
+        // The opaque column of a centered sprite spans x in [-2, -1].
+        let left = GlobalTransform::from_translation(Vec3::new(-3.0, 0.0, 0.0));
+        let right = GlobalTransform::from_translation(Vec3::new(3.0, 0.0, 0.0));
+        let a = posed(&aseprite, &sprite, &origin);
+        assert_eq!(
+            pixel_overlap(&a, &posed(&aseprite, &sprite, &right)),
+            Some(false)
+        );
+        // Flipped, the opaque column moves to [4, 5], next to the other sprite's [-2, -1]...
+        assert_eq!(
+            pixel_overlap(&a, &posed(&aseprite, &flipped, &right)),
+            Some(false)
+        );
+        // ...and from the left it lands on [-2, -1].
+        assert_eq!(
+            pixel_overlap(&a, &posed(&aseprite, &flipped, &left)),
+            Some(true)
+        );
+
+        // Anchored at the bottom left, the column spans [0, 1] and misses [-2, -1].
+        let b = posed(&aseprite, &anchored, &origin);
+        assert_eq!(pixel_overlap(&a, &b), Some(false));
+        let shifted = GlobalTransform::from_translation(Vec3::new(-2.0, -1.0, 0.0));
+        assert_eq!(
+            pixel_overlap(&a, &posed(&aseprite, &anchored, &shifted)),
+            Some(true)
+        );
+
+        // Scaled by 3 around x = 4, the column spans [-2, 1].
+        let scaled = GlobalTransform::from(
+            Transform::from_translation(Vec3::new(4.0, 0.0, 0.0)).with_scale(Vec3::splat(3.0)),
+        );
+        assert_eq!(
+            pixel_overlap(&a, &posed(&aseprite, &sprite, &scaled)),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn missing_mask_is_none() {
+        let mut aseprite = empty_aseprite(1);
+        aseprite.alpha_masks = vec![left_column()];
+        let sprite = TextureAtlasSprite::default();
+        let out_of_range = TextureAtlasSprite::new(1);
+        let origin = GlobalTransform::IDENTITY;
+        let missing = posed(&aseprite, &out_of_range, &origin);
+        assert!(missing.mask().is_none());
+        assert!(!missing.is_opaque_at(Vec2::ZERO));
+        assert_eq!(
+            pixel_overlap(&posed(&aseprite, &sprite, &origin), &missing),
+            None
+        );
+    }
+}
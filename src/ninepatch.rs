@@ -0,0 +1,319 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+
+/// A piece of a ninepatch: `source` is the texture region in pixels (y down) and `target` the
+/// area it covers in local space (y up).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NinepatchPiece {
+    pub source: Rect,
+    pub target: Rect,
+}
+
+/// How ninepatch edges and center fill their area.
+#[derive(Component, Clone, Copy, Default, Debug, Eq, PartialEq)]
+pub enum NinepatchFill {
+    #[default]
+    Stretch,
+    /// Repeat the source region at its native size, clipping the last repetition.
+    Tile,
+}
+
+// Borders that don't fit in `min..max` shrink proportionally.
+fn splits(min: f32, max: f32, first: f32, last: f32) -> [f32; 4] {
+    let scale = if first + last > max - min {
+        (max - min).max(0.0) / (first + last)
+    } else {
+        1.0
+    };
+    [min, min + first * scale, max - last * scale, max]
+}
+
+// Repeats `piece.source` over `piece.target` from the top left, clipping the last repetitions.
+fn tile_piece(piece: NinepatchPiece, tile_x: bool, tile_y: bool, pieces: &mut Vec<NinepatchPiece>) {
+    let source_size = piece.source.size();
+    let step = Vec2::new(
+        if tile_x {
+            source_size.x
+        } else {
+            piece.target.width()
+        },
+        if tile_y {
+            source_size.y
+        } else {
+            piece.target.height()
+        },
+    );
+    let mut top = piece.target.max.y;
+    while top > piece.target.min.y {
+        let bottom = (top - step.y).max(piece.target.min.y);
+        let mut left = piece.target.min.x;
+        while left < piece.target.max.x {
+            let right = (left + step.x).min(piece.target.max.x);
+            let source_max = Vec2::new(
+                if tile_x {
+                    piece.source.min.x + right - left
+                } else {
+                    piece.source.max.x
+                },
+                if tile_y {
+                    piece.source.min.y + top - bottom
+                } else {
+                    piece.source.max.y
+                },
+            );
+            pieces.push(NinepatchPiece {
+                source: Rect::from_corners(piece.source.min, source_max),
+                target: Rect::new(left, bottom, right, top),
+            });
+            left = right;
+        }
+        top = bottom;
+    }
+}
+
+/// Lays out a ninepatch of `size` centered on the origin. `source` is the slice region in the
+/// texture and `center` the ninepatch center relative to the slice. Corners keep their size,
+/// shrinking only when `size` is smaller than the borders, and edges and center are stretched or
+/// tiled according to `fill`. Empty pieces are skipped.
+pub fn ninepatch_pieces(
+    source: Rect,
+    center: Rect,
+    size: Vec2,
+    fill: NinepatchFill,
+) -> Vec<NinepatchPiece> {
+    let border_min = center.min;
+    let border_max = source.size() - center.max;
+    let source_x = [
+        source.min.x,
+        source.min.x + center.min.x,
+        source.min.x + center.max.x,
+        source.max.x,
+    ];
+    let source_y = [
+        source.min.y,
+        source.min.y + center.min.y,
+        source.min.y + center.max.y,
+        source.max.y,
+    ];
+    let target_x = splits(-size.x / 2.0, size.x / 2.0, border_min.x, border_max.x);
+    // Target rows run top to bottom to match the source rows.
+    let target_y = splits(-size.y / 2.0, size.y / 2.0, border_max.y, border_min.y);
+    let mut pieces = Vec::with_capacity(9);
+    for row in 0..3 {
+        for column in 0..3 {
+            let piece = NinepatchPiece {
+                source: Rect::new(
+                    source_x[column],
+                    source_y[row],
+                    source_x[column + 1],
+                    source_y[row + 1],
+                ),
+                target: Rect::new(
+                    target_x[column],
+                    target_y[2 - row],
+                    target_x[column + 1],
+                    target_y[3 - row],
+                ),
+            };
+            if piece.source.is_empty() || piece.target.is_empty() {
+                continue;
+            }
+            match fill {
+                NinepatchFill::Tile if column == 1 || row == 1 => {
+                    tile_piece(piece, column == 1, row == 1, &mut pieces)
+                }
+                _ => pieces.push(piece),
+            }
+        }
+    }
+    pieces
+}
+
+/// Builds a single mesh with one quad per piece. UVs are normalized by `texture_size`, the size
+/// of the texture the piece sources are taken from.
+pub fn ninepatch_mesh(pieces: &[NinepatchPiece], texture_size: Vec2) -> Mesh {
+    let mut positions = Vec::with_capacity(pieces.len() * 4);
+    let mut uvs = Vec::with_capacity(pieces.len() * 4);
+    let mut indices = Vec::with_capacity(pieces.len() * 6);
+    for piece in pieces {
+        let base = positions.len() as u32;
+        let uv_min = piece.source.min / texture_size;
+        let uv_max = piece.source.max / texture_size;
+        // Targets are y up while sources are y down, so the top of the target samples `uv_min.y`.
+        positions.extend([
+            [piece.target.min.x, piece.target.max.y, 0.0],
+            [piece.target.max.x, piece.target.max.y, 0.0],
+            [piece.target.max.x, piece.target.min.y, 0.0],
+            [piece.target.min.x, piece.target.min.y, 0.0],
+        ]);
+        uvs.extend([
+            [uv_min.x, uv_min.y],
+            [uv_max.x, uv_min.y],
+            [uv_max.x, uv_max.y],
+            [uv_min.x, uv_max.y],
+        ]);
+        indices.extend([base, base + 2, base + 1, base, base + 3, base + 2]);
+    }
+    let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    // A 6x6 slice at (10, 20) with 2px borders around a 2x2 center.
+    const SOURCE: Rect = Rect {
+        min: Vec2::new(10.0, 20.0),
+        max: Vec2::new(16.0, 26.0),
+    };
+    const CENTER: Rect = Rect {
+        min: Vec2::new(2.0, 2.0),
+        max: Vec2::new(4.0, 4.0),
+    };
+    const TEXTURE_SIZE: Vec2 = Vec2::new(32.0, 32.0);
+
+    fn piece(source: [f32; 4], target: [f32; 4]) -> NinepatchPiece {
+        NinepatchPiece {
+            source: Rect::new(source[0], source[1], source[2], source[3]),
+            target: Rect::new(target[0], target[1], target[2], target[3]),
+        }
+    }
+
+    fn positions(mesh: &Mesh) -> &[[f32; 3]] {
+        match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => panic!("missing positions"),
+        }
+    }
+
+    fn uvs(mesh: &Mesh) -> &[[f32; 2]] {
+        match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => uvs,
+            _ => panic!("missing uvs"),
+        }
+    }
+
+    fn indices(mesh: &Mesh) -> &[u32] {
+        match mesh.indices() {
+            Some(Indices::U32(indices)) => indices,
+            _ => panic!("missing indices"),
+        }
+    }
+
+    #[test]
+    fn stretch_pieces() {
+        let pieces = ninepatch_pieces(SOURCE, CENTER, Vec2::new(10.0, 8.0), NinepatchFill::Stretch);
+        assert_eq!(pieces.len(), 9);
+        assert_eq!(
+            pieces[0],
+            piece([10.0, 20.0, 12.0, 22.0], [-5.0, 2.0, -3.0, 4.0])
+        );
+        assert_eq!(
+            pieces[4],
+            piece([12.0, 22.0, 14.0, 24.0], [-3.0, -2.0, 3.0, 2.0])
+        );
+        assert_eq!(
+            pieces[8],
+            piece([14.0, 24.0, 16.0, 26.0], [3.0, -4.0, 5.0, -2.0])
+        );
+    }
+
+    #[test]
+    fn stretch_mesh() {
+        let pieces = ninepatch_pieces(SOURCE, CENTER, Vec2::new(10.0, 8.0), NinepatchFill::Stretch);
+        let mesh = ninepatch_mesh(&pieces, TEXTURE_SIZE);
+        assert_eq!(positions(&mesh).len(), 36);
+        assert_eq!(uvs(&mesh).len(), 36);
+        assert_eq!(indices(&mesh).len(), 54);
+        // The top-left corner, clockwise from its top-left vertex.
+        assert_eq!(
+            positions(&mesh)[..4],
+            [
+                [-5.0, 4.0, 0.0],
+                [-3.0, 4.0, 0.0],
+                [-3.0, 2.0, 0.0],
+                [-5.0, 2.0, 0.0]
+            ]
+        );
+        assert_eq!(
+            uvs(&mesh)[..4],
+            [
+                [0.3125, 0.625],
+                [0.375, 0.625],
+                [0.375, 0.6875],
+                [0.3125, 0.6875]
+            ]
+        );
+        assert_eq!(indices(&mesh)[..12], [0, 2, 1, 0, 3, 2, 4, 6, 5, 4, 7, 6]);
+    }
+
+    #[test]
+    fn tile_pieces_and_mesh() {
+        // The 5px wide center column repeats the 2px source twice and clips the third copy.
+        let pieces = ninepatch_pieces(SOURCE, CENTER, Vec2::new(9.0, 6.0), NinepatchFill::Tile);
+        assert_eq!(pieces.len(), 15);
+        assert_eq!(
+            pieces[1..4],
+            [
+                piece([12.0, 20.0, 14.0, 22.0], [-2.5, 1.0, -0.5, 3.0]),
+                piece([12.0, 20.0, 14.0, 22.0], [-0.5, 1.0, 1.5, 3.0]),
+                piece([12.0, 20.0, 13.0, 22.0], [1.5, 1.0, 2.5, 3.0]),
+            ]
+        );
+        let mesh = ninepatch_mesh(&pieces, TEXTURE_SIZE);
+        assert_eq!(positions(&mesh).len(), 60);
+        assert_eq!(indices(&mesh).len(), 90);
+        // The clipped tile samples only the first half of the source.
+        assert_eq!(
+            uvs(&mesh)[12..16],
+            [
+                [0.375, 0.625],
+                [0.40625, 0.625],
+                [0.40625, 0.6875],
+                [0.375, 0.6875]
+            ]
+        );
+    }
+
+    #[test]
+    fn border_only_size_keeps_corners() {
+        for fill in [NinepatchFill::Stretch, NinepatchFill::Tile] {
+            let pieces = ninepatch_pieces(SOURCE, CENTER, Vec2::new(4.0, 4.0), fill);
+            assert_eq!(
+                pieces,
+                vec![
+                    piece([10.0, 20.0, 12.0, 22.0], [-2.0, 0.0, 0.0, 2.0]),
+                    piece([14.0, 20.0, 16.0, 22.0], [0.0, 0.0, 2.0, 2.0]),
+                    piece([10.0, 24.0, 12.0, 26.0], [-2.0, -2.0, 0.0, 0.0]),
+                    piece([14.0, 24.0, 16.0, 26.0], [0.0, -2.0, 2.0, 0.0]),
+                ]
+            );
+            assert_eq!(indices(&ninepatch_mesh(&pieces, TEXTURE_SIZE)).len(), 24);
+        }
+    }
+
+    #[test]
+    fn size_smaller_than_corners_shrinks_them() {
+        for fill in [NinepatchFill::Stretch, NinepatchFill::Tile] {
+            let pieces = ninepatch_pieces(SOURCE, CENTER, Vec2::new(2.0, 1.0), fill);
+            assert_eq!(
+                pieces,
+                vec![
+                    piece([10.0, 20.0, 12.0, 22.0], [-1.0, 0.0, 0.0, 0.5]),
+                    piece([14.0, 20.0, 16.0, 22.0], [0.0, 0.0, 1.0, 0.5]),
+                    piece([10.0, 24.0, 12.0, 26.0], [-1.0, -0.5, 0.0, 0.0]),
+                    piece([14.0, 24.0, 16.0, 26.0], [0.0, -0.5, 1.0, 0.0]),
+                ]
+            );
+        }
+        assert!(ninepatch_pieces(SOURCE, CENTER, Vec2::ZERO, NinepatchFill::Stretch).is_empty());
+    }
+}
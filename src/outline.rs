@@ -0,0 +1,215 @@
+use crate::mask::AlphaMask;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// Traces the opaque regions of a mask into closed polygons, in the mask's pixel space (origin
+/// at the top left, y pointing down). Holes are traced as separate polygons with the opposite
+/// winding. Points are reduced with Ramer-Douglas-Peucker using `tolerance` pixels.
+pub fn trace_outlines(mask: &AlphaMask, tolerance: f32) -> Vec<Vec<Vec2>> {
+    // Boundary edges of opaque pixels, oriented so the opaque side is always on the same hand.
+    let mut edges: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+    let mut add_edge = |from: IVec2, to: IVec2| edges.entry(from).or_default().push(to);
+    for y in 0..mask.height() as i32 {
+        for x in 0..mask.width() as i32 {
+            if !mask.get(x, y) {
+                continue;
+            }
+            if !mask.get(x, y - 1) {
+                add_edge(IVec2::new(x + 1, y), IVec2::new(x, y));
+            }
+            if !mask.get(x - 1, y) {
+                add_edge(IVec2::new(x, y), IVec2::new(x, y + 1));
+            }
+            if !mask.get(x, y + 1) {
+                add_edge(IVec2::new(x, y + 1), IVec2::new(x + 1, y + 1));
+            }
+            if !mask.get(x + 1, y) {
+                add_edge(IVec2::new(x + 1, y + 1), IVec2::new(x + 1, y));
+            }
+        }
+    }
+
+    let mut outlines = Vec::new();
+    // The top-left vertex never joins two regions, so tracing can start anywhere from it.
+    while let Some(&start) = edges.keys().min_by_key(|point| (point.y, point.x)) {
+        let mut contour = vec![start];
+        let mut current = start;
+        let mut direction = IVec2::ZERO;
+        loop {
+            // Where pixels touch only at a corner, keep turning the same way so each region is
+            // traced on its own.
+            let next = match edges.get_mut(&current).and_then(|ends| {
+                let i = (0..ends.len()).min_by_key(|&i| direction.perp_dot(ends[i] - current))?;
+                Some(ends.swap_remove(i))
+            }) {
+                Some(next) => next,
+                None => break,
+            };
+            direction = next - current;
+            if edges.get(&current).map_or(false, |ends| ends.is_empty()) {
+                edges.remove(&current);
+            }
+            if next == start {
+                break;
+            }
+            contour.push(next);
+            current = next;
+        }
+        let points = simplify_closed(&remove_collinear(&contour), tolerance);
+        // A large tolerance can collapse a contour to a line, which is no polygon.
+        if points.len() >= 3 {
+            outlines.push(points);
+        }
+    }
+    outlines
+}
+
+fn remove_collinear(points: &[IVec2]) -> Vec<Vec2> {
+    let n = points.len();
+    (0..n)
+        .filter(|&i| {
+            let prev = points[(i + n - 1) % n];
+            let next = points[(i + 1) % n];
+            (points[i] - prev).perp_dot(next - points[i]) != 0
+        })
+        .map(|i| points[i].as_vec2())
+        .collect()
+}
+
+fn simplify_closed(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if tolerance <= 0.0 || points.len() <= 3 {
+        return points.to_vec();
+    }
+    let far = (1..points.len())
+        .max_by(|&a, &b| {
+            points[a]
+                .distance_squared(points[0])
+                .total_cmp(&points[b].distance_squared(points[0]))
+        })
+        .unwrap();
+    let mut first: Vec<Vec2> = points[..=far].to_vec();
+    let mut second: Vec<Vec2> = points[far..].to_vec();
+    second.push(points[0]);
+    first = simplify(&first, tolerance);
+    second = simplify(&second, tolerance);
+    first.pop();
+    second.pop();
+    first.extend(second);
+    first
+}
+
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (index, distance) = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i + 1, distance_to_segment(*point, first, last)))
+        .fold(
+            (0, 0.0),
+            |max, item| if item.1 > max.1 { item } else { max },
+        );
+    if distance <= tolerance {
+        return vec![first, last];
+    }
+    let mut result = simplify(&points[..=index], tolerance);
+    result.pop();
+    result.extend(simplify(&points[index..], tolerance));
+    result
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab == Vec2::ZERO {
+        0.0
+    } else {
+        ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    };
+    point.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn mask(width: u32, height: u32, opaque: &[(u32, u32)]) -> AlphaMask {
+        let mut image = RgbaImage::new(width, height);
+        for (x, y) in opaque {
+            image.put_pixel(*x, *y, Rgba([0, 0, 0, 255]));
+        }
+        AlphaMask::from_image(&image, 0)
+    }
+
+    fn points(points: &[(f32, f32)]) -> Vec<Vec2> {
+        points.iter().map(|(x, y)| Vec2::new(*x, *y)).collect()
+    }
+
+    fn signed_area(polygon: &[Vec2]) -> f32 {
+        let n = polygon.len();
+        (0..n)
+            .map(|i| polygon[i].perp_dot(polygon[(i + 1) % n]))
+            .sum::<f32>()
+            / 2.0
+    }
+
+    #[test]
+    fn square() {
+        let outlines = trace_outlines(&mask(2, 2, &[(0, 0), (1, 0), (0, 1), (1, 1)]), 0.5);
+        assert_eq!(
+            outlines,
+            vec![points(&[(0., 0.), (0., 2.), (2., 2.), (2., 0.)])]
+        );
+    }
+
+    #[test]
+    fn l_shape() {
+        let outlines = trace_outlines(&mask(2, 2, &[(0, 0), (0, 1), (1, 1)]), 0.0);
+        assert_eq!(
+            outlines,
+            vec![points(&[
+                (0., 0.),
+                (0., 2.),
+                (2., 2.),
+                (2., 1.),
+                (1., 1.),
+                (1., 0.)
+            ])]
+        );
+    }
+
+    #[test]
+    fn hole_has_opposite_winding() {
+        let ring: Vec<_> = (0..3)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .filter(|&pixel| pixel != (1, 1))
+            .collect();
+        let outlines = trace_outlines(&mask(3, 3, &ring), 0.0);
+        assert_eq!(outlines.len(), 2);
+        let mut areas: Vec<f32> = outlines
+            .iter()
+            .map(|outline| signed_area(outline))
+            .collect();
+        areas.sort_by(|a, b| a.abs().total_cmp(&b.abs()));
+        assert_eq!(areas[0].abs(), 1.0);
+        assert_eq!(areas[1].abs(), 9.0);
+        assert!(areas[0].signum() != areas[1].signum());
+    }
+
+    #[test]
+    fn diagonal_pixels_are_separate() {
+        let outlines = trace_outlines(&mask(2, 2, &[(0, 0), (1, 1)]), 0.0);
+        assert_eq!(
+            outlines,
+            vec![
+                points(&[(0., 0.), (0., 1.), (1., 1.), (1., 0.)]),
+                points(&[(1., 1.), (1., 2.), (2., 2.), (2., 1.)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed_outlines_are_dropped() {
+        let outlines = trace_outlines(&mask(2, 2, &[(0, 0), (1, 0), (0, 1), (1, 1)]), 5.0);
+        assert!(outlines.is_empty());
+    }
+}
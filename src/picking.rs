@@ -0,0 +1,130 @@
+use crate::assets::Aseprite;
+use crate::mask::PosedSprite;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+/// Finds the topmost visible Aseprite sprite with an opaque pixel under a world-space point.
+#[derive(SystemParam)]
+pub struct AsepritePicking<'w, 's> {
+    aseprites: Res<'w, Assets<Aseprite>>,
+    query: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static Handle<Aseprite>,
+            &'static TextureAtlasSprite,
+            &'static GlobalTransform,
+            &'static ComputedVisibility,
+        ),
+    >,
+}
+
+impl<'w, 's> AsepritePicking<'w, 's> {
+    pub fn pick(&self, point: Vec2) -> Option<Entity> {
+        pick_topmost(
+            &self.aseprites,
+            self.query
+                .iter()
+                .map(|(entity, aseprite_handle, sprite, transform, visibility)| {
+                    (
+                        entity,
+                        aseprite_handle,
+                        sprite,
+                        transform,
+                        visibility.is_visible(),
+                    )
+                }),
+            point,
+        )
+    }
+
+    pub fn pick_viewport(
+        &self,
+        camera: &Camera,
+        camera_transform: &GlobalTransform,
+        viewport_position: Vec2,
+    ) -> Option<Entity> {
+        self.pick(camera.viewport_to_world_2d(camera_transform, viewport_position)?)
+    }
+}
+
+// The sprite with the highest z among the visible ones with an opaque pixel under `point`.
+fn pick_topmost<'a>(
+    aseprites: &Assets<Aseprite>,
+    sprites: impl Iterator<
+        Item = (
+            Entity,
+            &'a Handle<Aseprite>,
+            &'a TextureAtlasSprite,
+            &'a GlobalTransform,
+            bool,
+        ),
+    >,
+    point: Vec2,
+) -> Option<Entity> {
+    sprites
+        .filter(|(_, _, _, _, visible)| *visible)
+        .filter_map(|(entity, aseprite_handle, sprite, transform, _)| {
+            let aseprite = aseprites.get(aseprite_handle)?;
+            let posed = PosedSprite {
+                aseprite,
+                sprite,
+                transform,
+            };
+            (posed.world_rect()?.contains(point) && posed.is_opaque_at(point))
+                .then_some((entity, transform.translation().z))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, _)| entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::AlphaMask;
+    use crate::test_utils::{asset_app, empty_aseprite};
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn picks_topmost_visible_opaque_sprite() {
+        // A 4x4 sprite whose right half is transparent.
+        let mut image = RgbaImage::new(4, 4);
+        for y in 0..4 {
+            for x in 0..2 {
+                image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        let mut aseprite = empty_aseprite(1);
+        aseprite.alpha_masks = vec![AlphaMask::from_image(&image, 0)];
+        let mut app = asset_app();
+        let mut aseprites = app.world.resource_mut::<Assets<Aseprite>>();
+        let handle = aseprites.add(aseprite);
+
+        let sprite = TextureAtlasSprite::default();
+        let back = GlobalTransform::from_xyz(0.0, 0.0, 1.0);
+        let front = GlobalTransform::from_xyz(0.0, 0.0, 2.0);
+        let hidden = GlobalTransform::from_xyz(0.0, 0.0, 3.0);
+        let entities: Vec<_> = (0..3).map(|_| app.world.spawn_empty().id()).collect();
+        let aseprites = app.world.resource::<Assets<Aseprite>>();
+        let pick = |point: Vec2| {
+            pick_topmost(
+                aseprites,
+                [
+                    (entities[0], &handle, &sprite, &back, true),
+                    (entities[1], &handle, &sprite, &front, true),
+                    (entities[2], &handle, &sprite, &hidden, false),
+                ]
+                .into_iter(),
+                point,
+            )
+        };
+
+        // The opaque half spans x in [-2, 0]; the hidden sprite on top is skipped.
+        assert_eq!(pick(Vec2::new(-1.0, 0.0)), Some(entities[1]));
+        // Inside the rects but over transparent pixels.
+        assert_eq!(pick(Vec2::new(1.0, 0.0)), None);
+        // Outside the rects.
+        assert_eq!(pick(Vec2::new(-3.0, 0.0)), None);
+    }
+}
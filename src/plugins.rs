@@ -1,20 +1,109 @@
-use crate::assets::{Aseprite, AsepriteLoader};
-use crate::systems::{animate_aseprite, fixup_aseprite_animation, fixup_texture_atlas};
-//use crate::ui::systems::{fixup_aseprite_animation_ui, animate_aseprite_ui, fixup_ninepatch_ui};
+use crate::assets::{Aseprite, AsepriteLoader, AsepriteLoaderSettings};
+use crate::components::AsepriteHitEvent;
+use crate::composites::{invalidate_aseprite_layer_composites, AsepriteLayerComposites};
+use crate::systems::{
+    animate_aseprite, detect_aseprite_hit_overlaps, fixup_aseprite_animation,
+    fixup_aseprite_equipment, fixup_aseprite_layers, fixup_texture_atlas, sync_aseprite_equipment,
+    update_aseprite_hitboxes, update_aseprite_ninepatch, update_aseprite_pivot,
+    update_aseprite_root_motion, update_aseprite_sockets,
+};
+use crate::tilemap::systems::{
+    animate_aseprite_tilemap, fixup_aseprite_tilemap, fixup_aseprite_tilemap_animation,
+};
+#[cfg(feature = "ui")]
+use crate::ui::components::AsepriteUiPixelScale;
+#[cfg(feature = "ui")]
+use crate::ui::systems::{
+    animate_aseprite_ui, apply_ninepatch_ui_layout, cleanup_aseprite_ui_children,
+    fixup_aseprite_animation_ui, fixup_ninepatch_ui, layout_ninepatch_ui, tile_ninepatch_ui,
+    update_aseprite_ui_button,
+};
 use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+#[cfg(feature = "ui")]
+use bevy::ui::UiSystem;
 
-pub struct AsepritePlugin;
+#[derive(Default)]
+pub struct AsepritePlugin {
+    pub loader_settings: AsepriteLoaderSettings,
+}
 
 impl Plugin for AsepritePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_asset::<Aseprite>()
-            .add_asset_loader(AsepriteLoader)
+            .add_asset_loader(AsepriteLoader {
+                settings: self.loader_settings.clone(),
+            })
+            .init_resource::<AsepriteLayerComposites>()
+            .add_systems(PreUpdate, invalidate_aseprite_layer_composites)
             .add_systems(PreUpdate, fixup_texture_atlas)
-            .add_systems(PreUpdate, fixup_aseprite_animation)
-            .add_systems(Update, animate_aseprite);
-        /*
-        .add_systems(PreUpdate, fixup_aseprite_animation_ui)
-        .add_systems(PreUpdate, fixup_ninepatch_ui)
-        .add_systems(Update, animate_aseprite_ui);*/
+            .add_systems(
+                PreUpdate,
+                fixup_aseprite_animation.after(fixup_texture_atlas),
+            )
+            .add_systems(PreUpdate, fixup_aseprite_layers)
+            .add_systems(PreUpdate, fixup_aseprite_equipment)
+            .add_systems(PreUpdate, fixup_aseprite_tilemap_animation)
+            .add_systems(
+                PreUpdate,
+                fixup_aseprite_tilemap.after(fixup_aseprite_tilemap_animation),
+            )
+            .add_systems(Update, animate_aseprite)
+            .add_systems(Update, update_aseprite_pivot.after(animate_aseprite))
+            .add_systems(
+                Update,
+                update_aseprite_root_motion
+                    .after(animate_aseprite)
+                    .after(update_aseprite_pivot),
+            )
+            .add_systems(
+                Update,
+                sync_aseprite_equipment
+                    .after(animate_aseprite)
+                    .after(update_aseprite_pivot)
+                    .after(update_aseprite_root_motion),
+            )
+            .add_systems(Update, update_aseprite_ninepatch.after(animate_aseprite))
+            .add_systems(
+                Update,
+                update_aseprite_sockets
+                    .after(animate_aseprite)
+                    .after(update_aseprite_pivot)
+                    .after(update_aseprite_root_motion),
+            )
+            .add_systems(Update, animate_aseprite_tilemap)
+            .add_systems(
+                PostUpdate,
+                update_aseprite_hitboxes.after(TransformSystem::TransformPropagate),
+            );
+        #[cfg(feature = "ui")]
+        app.init_resource::<AsepriteUiPixelScale>()
+            .add_systems(
+                PreUpdate,
+                fixup_aseprite_animation_ui.after(fixup_texture_atlas),
+            )
+            .add_systems(
+                PreUpdate,
+                fixup_ninepatch_ui.after(fixup_aseprite_animation_ui),
+            )
+            .add_systems(PreUpdate, cleanup_aseprite_ui_children)
+            .add_systems(Update, animate_aseprite_ui)
+            .add_systems(Update, update_aseprite_ui_button)
+            .add_systems(Update, layout_ninepatch_ui.after(animate_aseprite_ui))
+            .add_systems(Update, apply_ninepatch_ui_layout.after(animate_aseprite_ui))
+            .add_systems(PostUpdate, tile_ninepatch_ui.after(UiSystem::Layout));
+    }
+}
+
+/// Emits `AsepriteHitEvent`s when hit boxes start or stop overlapping hurt boxes, as configured
+/// by `AsepriteHitboxLayers`.
+pub struct AsepriteHitboxPlugin;
+
+impl Plugin for AsepriteHitboxPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.add_event::<AsepriteHitEvent>().add_systems(
+            PostUpdate,
+            detect_aseprite_hit_overlaps.after(update_aseprite_hitboxes),
+        );
     }
 }
@@ -1,15 +1,25 @@
 use crate::assets::Aseprite;
-use crate::components::{AsepriteAnimation, AsepriteAtlas};
+use crate::components::{
+    AnimationDirection, AsepriteAnimation, AsepriteAtlas, AsepriteBundle, AsepriteEquipment,
+    AsepriteEquipmentChildren, AsepriteEquipmentItem, AsepriteHitEvent, AsepriteHitOverlap,
+    AsepriteHitbox, AsepriteHitboxLayers, AsepriteHitboxes, AsepriteLayers, AsepriteLayersChildren,
+    AsepriteNinepatch, AsepriteNinepatchMesh, AsepritePivot, AsepriteRootMotion, AsepriteSocket,
+};
+use crate::ninepatch::{ninepatch_mesh, ninepatch_pieces};
 use crate::utils::coalesce;
 use bevy::prelude::*;
+use bevy::sprite::{Anchor, Mesh2dHandle};
+use std::collections::HashSet;
 use std::ops::DerefMut;
 
 // TODO: use AssetChanged query condition after https://github.com/bevyengine/bevy/pull/5080 merged
+// Entities without a `Handle<TextureAtlas>`, such as ninepatches and UI nodes, still get their
+// `AsepriteAtlas` marked as changed so their animation is fixed up once the asset loads.
 pub fn fixup_texture_atlas(
     aseprites: Res<Assets<Aseprite>>,
     mut query: Query<(
         &Handle<Aseprite>,
-        &mut Handle<TextureAtlas>,
+        Option<&mut Handle<TextureAtlas>>,
         &mut AsepriteAtlas,
     )>,
     mut ev_asset: EventReader<AssetEvent<Aseprite>>,
@@ -17,10 +27,12 @@ pub fn fixup_texture_atlas(
     for ev in ev_asset.iter() {
         match ev {
             AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
-                for (aseprite_handle, mut texture_atlas, mut aseprite_atlas) in query.iter_mut() {
+                for (aseprite_handle, texture_atlas, mut aseprite_atlas) in query.iter_mut() {
                     if aseprite_handle.id() == handle.id() {
                         if let Some(aseprite) = aseprites.get(aseprite_handle) {
-                            *texture_atlas = aseprite.atlas.clone();
+                            if let Some(mut texture_atlas) = texture_atlas {
+                                *texture_atlas = aseprite.atlas.clone();
+                            }
                             aseprite_atlas.deref_mut();
                         }
                     }
@@ -38,32 +50,843 @@ pub fn fixup_aseprite_animation(
             &Handle<Aseprite>,
             &AsepriteAtlas,
             &mut AsepriteAnimation,
-            &mut TextureAtlasSprite,
+            Option<&mut TextureAtlasSprite>,
+        ),
+        (
+            Changed<AsepriteAtlas>,
+            Or<(With<TextureAtlasSprite>, With<AsepriteNinepatch>)>,
         ),
-        Changed<AsepriteAtlas>,
     >,
 ) {
-    for (aseprite_handle, ase_atlas, mut ase_anim, mut sprite) in query.iter_mut() {
+    for (aseprite_handle, ase_atlas, mut ase_anim, sprite) in query.iter_mut() {
         let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
-        sprite.index = ase_anim.fixup(ase_atlas, aseprite);
+        let index = ase_anim.fixup(ase_atlas, aseprite);
+        if let Some(mut sprite) = sprite {
+            sprite.index = index;
+        }
     }
 }
 
 pub fn animate_aseprite(
     time: Res<Time>,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<
+        (
+            &Handle<Aseprite>,
+            &mut AsepriteAnimation,
+            Option<&mut TextureAtlasSprite>,
+        ),
+        Or<(With<TextureAtlasSprite>, With<AsepriteNinepatch>)>,
+    >,
+) {
+    for (aseprite_handle, mut ase_anim, sprite) in query.iter_mut() {
+        if let Some(aseprite) = aseprites.get(aseprite_handle) {
+            let next_index = ase_anim.step(time.delta(), aseprite);
+            if let Some(mut sprite) = sprite {
+                if sprite.index != next_index {
+                    sprite.index = next_index;
+                }
+            }
+        }
+    }
+}
+
+// Position of a canvas-space point within `region`, from 0 to 1 and flipped along with the sprite.
+fn region_offset(point: Vec2, region: Rect, sprite: &TextureAtlasSprite) -> Vec2 {
+    let mut offset = (point - region.min) / region.size();
+    if sprite.flip_x {
+        offset.x = 1.0 - offset.x;
+    }
+    if sprite.flip_y {
+        offset.y = 1.0 - offset.y;
+    }
+    offset
+}
+
+pub(crate) fn pivot_anchor(point: Vec2, region: Rect, sprite: &TextureAtlasSprite) -> Anchor {
+    let offset = region_offset(point, region, sprite);
+    Anchor::Custom(Vec2::new(offset.x - 0.5, 0.5 - offset.y))
+}
+
+/// Maps a canvas-space point to the sprite's local space, honoring anchor, flip and custom size.
+pub(crate) fn canvas_to_local(point: Vec2, region: Rect, sprite: &TextureAtlasSprite) -> Vec2 {
+    let offset = region_offset(point, region, sprite);
+    let anchor = sprite.anchor.as_vec();
+    let size = sprite.custom_size.unwrap_or(region.size());
+    Vec2::new(offset.x - 0.5 - anchor.x, 0.5 - offset.y - anchor.y) * size
+}
+
+pub(crate) fn local_to_world(rect: Rect, transform: &GlobalTransform) -> Rect {
+    [
+        rect.min,
+        rect.max,
+        Vec2::new(rect.min.x, rect.max.y),
+        Vec2::new(rect.max.x, rect.min.y),
+    ]
+    .into_iter()
+    .map(|corner| transform.transform_point(corner.extend(0.0)).truncate())
+    .fold(
+        Rect::new(f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |rect, corner| Rect::from_corners(rect.min.min(corner), rect.max.max(corner)),
+    )
+}
+
+pub fn update_aseprite_pivot(
     aseprites: Res<Assets<Aseprite>>,
     mut query: Query<(
         &Handle<Aseprite>,
-        &mut AsepriteAnimation,
+        &AsepriteAtlas,
+        &AsepriteAnimation,
+        &AsepritePivot,
         &mut TextureAtlasSprite,
     )>,
 ) {
-    for (aseprite_handle, mut ase_anim, mut sprite) in query.iter_mut() {
-        if let Some(aseprite) = aseprites.get(aseprite_handle) {
-            let next_index = ase_anim.step(time.delta(), aseprite);
-            if sprite.index != next_index {
-                sprite.index = next_index;
+    for (aseprite_handle, ase_atlas, ase_anim, pivot, mut sprite) in query.iter_mut() {
+        let slice = coalesce!(pivot.slice, continue);
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.current_frame() as usize;
+        let point = coalesce!(aseprite.slice_pivot(slice, frame), continue);
+        let anchor = pivot_anchor(point, aseprite.atlas_region(ase_atlas, frame), &sprite);
+        if sprite.anchor.as_vec() != anchor.as_vec() {
+            sprite.anchor = anchor;
+        }
+    }
+}
+
+pub fn update_aseprite_hitboxes(
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        &Handle<Aseprite>,
+        &AsepriteAtlas,
+        &AsepriteAnimation,
+        &TextureAtlasSprite,
+        &GlobalTransform,
+        &mut AsepriteHitboxes,
+    )>,
+) {
+    for (aseprite_handle, ase_atlas, ase_anim, sprite, transform, mut hitboxes) in query.iter_mut()
+    {
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.current_frame() as usize;
+        let region = aseprite.atlas_region(ase_atlas, frame);
+        let hitboxes = hitboxes.as_mut();
+        hitboxes.hitboxes.clear();
+        for &name in hitboxes.slices.iter() {
+            let slice = coalesce!(aseprite.find_slice(name), continue);
+            if hitboxes.user_data.is_some() && slice.user_data.as_deref() != hitboxes.user_data {
+                continue;
+            }
+            let rect = coalesce!(slice.segment(frame), continue).rect();
+            let local = Rect::from_corners(
+                canvas_to_local(rect.min, region, sprite),
+                canvas_to_local(rect.max, region, sprite),
+            );
+            hitboxes.hitboxes.push(AsepriteHitbox {
+                slice: name,
+                local,
+                world: local_to_world(local, transform),
+            });
+        }
+    }
+}
+
+pub fn detect_aseprite_hit_overlaps(
+    query: Query<(Entity, &AsepriteHitboxes, &AsepriteHitboxLayers)>,
+    mut overlaps: Local<HashSet<AsepriteHitOverlap>>,
+    mut ev_hit: EventWriter<AsepriteHitEvent>,
+) {
+    let mut current = HashSet::new();
+    for (hitter, hitter_boxes, hitter_layers) in query.iter() {
+        for (target, target_boxes, target_layers) in query.iter() {
+            if hitter == target || hitter_layers.mask & target_layers.layers == 0 {
+                continue;
+            }
+            for hit in hitter_boxes
+                .hitboxes
+                .iter()
+                .filter(|hitbox| hitter_layers.hit.contains(&hitbox.slice))
+            {
+                for hurt in target_boxes
+                    .hitboxes
+                    .iter()
+                    .filter(|hitbox| target_layers.hurt.contains(&hitbox.slice))
+                {
+                    if !hit.world.intersect(hurt.world).is_empty() {
+                        current.insert(AsepriteHitOverlap {
+                            hitter,
+                            hit_slice: hit.slice,
+                            target,
+                            hurt_slice: hurt.slice,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for overlap in current.difference(&overlaps) {
+        ev_hit.send(AsepriteHitEvent::Started(*overlap));
+    }
+    for overlap in overlaps.difference(&current) {
+        ev_hit.send(AsepriteHitEvent::Ended(*overlap));
+    }
+    *overlaps = current;
+}
+
+pub fn update_aseprite_sockets(
+    aseprites: Res<Assets<Aseprite>>,
+    parents: Query<(
+        &Handle<Aseprite>,
+        &AsepriteAtlas,
+        &AsepriteAnimation,
+        &TextureAtlasSprite,
+    )>,
+    mut query: Query<(&Parent, &AsepriteSocket, &mut Transform)>,
+) {
+    for (parent, socket, mut transform) in query.iter_mut() {
+        let (aseprite_handle, ase_atlas, ase_anim, sprite) =
+            coalesce!(parents.get(parent.get()).ok(), continue);
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.current_frame() as usize;
+        let slice = coalesce!(aseprite.find_slice(socket.slice), continue);
+        let segment = coalesce!(slice.segment(frame), continue);
+        let point = match segment.pivot {
+            Some(pivot) if socket.pivot => segment.origin + pivot,
+            _ => segment.rect().center(),
+        };
+        let local = canvas_to_local(point, aseprite.atlas_region(ase_atlas, frame), sprite);
+        transform.translation.x = local.x;
+        transform.translation.y = local.y;
+        if socket.mirror {
+            transform.scale.x = transform.scale.x.abs() * if sprite.flip_x { -1.0 } else { 1.0 };
+            transform.scale.y = transform.scale.y.abs() * if sprite.flip_y { -1.0 } else { 1.0 };
+        }
+    }
+}
+
+fn root_motion_point(aseprite: &Aseprite, slice: &str, frame: u32) -> Option<Vec2> {
+    let segment = aseprite.find_slice(slice)?.segment(frame as usize)?;
+    Some(match segment.pivot {
+        Some(pivot) => segment.origin + pivot,
+        None => segment.rect().center(),
+    })
+}
+
+pub fn update_aseprite_root_motion(
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        &Handle<Aseprite>,
+        &AsepriteAtlas,
+        &AsepriteAnimation,
+        &mut AsepriteRootMotion,
+        &mut TextureAtlasSprite,
+        &mut Transform,
+    )>,
+) {
+    for (aseprite_handle, ase_atlas, ase_anim, mut root_motion, mut sprite, mut transform) in
+        query.iter_mut()
+    {
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.current_frame();
+        if root_motion.last_frame_range != ase_anim.frame_range {
+            root_motion.last_frame_range = ase_anim.frame_range.clone();
+            root_motion.last_frame = None;
+        }
+        if root_motion.last_frame == Some(frame) {
+            if root_motion.delta != Vec2::ZERO {
+                root_motion.delta = Vec2::ZERO;
+            }
+            continue;
+        }
+        let point = coalesce!(
+            root_motion_point(aseprite, root_motion.slice, frame),
+            continue
+        );
+        let region = aseprite.atlas_region(ase_atlas, frame as usize);
+        sprite.anchor = pivot_anchor(point, region, &sprite);
+
+        // Restarting a loop moves from the first frame of the range, not back from the last one.
+        let restarted = match (root_motion.last_frame, &ase_anim.direction) {
+            (Some(last), AnimationDirection::Forward) => frame < last,
+            (Some(last), AnimationDirection::Backward) => frame > last,
+            _ => false,
+        };
+        let from = if restarted {
+            let first = match ase_anim.direction {
+                AnimationDirection::Backward => ase_anim.frame_range.end - 1,
+                _ => ase_anim.frame_range.start,
+            };
+            root_motion_point(aseprite, root_motion.slice, first).unwrap_or(point)
+        } else {
+            root_motion.last_pivot
+        };
+        let mut delta = match root_motion.last_frame {
+            Some(_) => (point - from) * sprite.custom_size.unwrap_or(region.size()) / region.size(),
+            None => Vec2::ZERO,
+        };
+        delta.y = -delta.y;
+        if sprite.flip_x {
+            delta.x = -delta.x;
+        }
+        if sprite.flip_y {
+            delta.y = -delta.y;
+        }
+        let delta = (transform.rotation * (delta.extend(0.0) * transform.scale)).truncate();
+        if root_motion.apply {
+            transform.translation += delta.extend(0.0);
+        }
+        root_motion.delta = delta;
+        root_motion.last_frame = Some(frame);
+        root_motion.last_pivot = point;
+    }
+}
+
+pub fn update_aseprite_ninepatch(
+    aseprites: Res<Assets<Aseprite>>,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut ev_asset: EventReader<AssetEvent<Aseprite>>,
+    mut query: Query<(
+        Ref<Handle<Aseprite>>,
+        Ref<AsepriteAtlas>,
+        &AsepriteAnimation,
+        Ref<AsepriteNinepatch>,
+        &mut AsepriteNinepatchMesh,
+        &mut Mesh2dHandle,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    let modified: HashSet<_> = ev_asset
+        .iter()
+        .filter_map(|ev| match ev {
+            AssetEvent::Modified { handle } => Some(handle.id()),
+            _ => None,
+        })
+        .collect();
+    for (aseprite_handle, ase_atlas, ase_anim, ninepatch, mut built, mut mesh, mut material) in
+        query.iter_mut()
+    {
+        let frame = ase_anim.current_frame();
+        if built.frame == Some(frame)
+            && !ninepatch.is_changed()
+            && !ase_atlas.is_changed()
+            && !aseprite_handle.is_changed()
+            && !modified.contains(&aseprite_handle.id())
+        {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(&*aseprite_handle), continue);
+        let atlas = coalesce!(atlases.get(&aseprite.atlas), continue);
+        let slice_name = coalesce!(ase_atlas.slice, continue);
+        let slice = aseprite.slice(slice_name, frame as usize);
+        let center = coalesce!(slice.ninepatch_center, continue);
+        let index = coalesce!(aseprite.slice_index(slice_name, frame), continue);
+        let pieces = ninepatch_pieces(
+            atlas.textures[index as usize],
+            center,
+            ninepatch.size,
+            ninepatch.fill,
+        );
+        built.frame = Some(frame);
+
+        // Default handles point at shared placeholder assets, so each ninepatch gets its own
+        // mesh and material instead of writing into those.
+        let new_mesh = ninepatch_mesh(&pieces, atlas.size);
+        match meshes.get_mut(&mesh.0) {
+            Some(existing) if mesh.0 != Handle::default() => *existing = new_mesh,
+            _ => mesh.0 = meshes.add(new_mesh),
+        }
+        match materials.get_mut(&*material) {
+            Some(existing) if *material != Handle::default() => {
+                if existing.texture.as_ref() != Some(&atlas.texture) {
+                    existing.texture = Some(atlas.texture.clone());
+                }
+            }
+            _ => *material = materials.add(ColorMaterial::from(atlas.texture.clone())),
+        }
+    }
+}
+
+const LAYER_Z_STEP: f32 = 0.001;
+
+pub fn fixup_aseprite_layers(
+    mut commands: Commands,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        Entity,
+        &Handle<Aseprite>,
+        Ref<AsepriteLayers>,
+        &mut AsepriteLayersChildren,
+    )>,
+    mut children_query: Query<(&mut AsepriteAtlas, &mut Transform)>,
+) {
+    for (entity, aseprite_handle, layers, mut children) in query.iter_mut() {
+        if !layers.is_changed() && children.len() == layers.layers.len() {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        if children.len() == layers.layers.len() {
+            for (child, layer) in children.iter().zip(layers.layers.iter()) {
+                if let Ok((mut child_atlas, mut transform)) = children_query.get_mut(*child) {
+                    let atlas = AsepriteAtlas {
+                        layer: Some(layer.layer),
+                        tag: layer.tag,
+                        ..Default::default()
+                    };
+                    if *child_atlas != atlas {
+                        *child_atlas = atlas;
+                    }
+                    transform.translation.z = aseprite.layer_id(layer.layer) as f32 * LAYER_Z_STEP;
+                }
+            }
+            continue;
+        }
+        for child in children.drain(..) {
+            commands.entity(child).despawn_recursive();
+        }
+        for layer in layers.layers.iter() {
+            let child = commands
+                .spawn(AsepriteBundle {
+                    aseprite: aseprite_handle.clone(),
+                    aseprite_atlas: AsepriteAtlas {
+                        layer: Some(layer.layer),
+                        tag: layer.tag,
+                        ..Default::default()
+                    },
+                    texture_atlas: aseprite.atlas.clone(),
+                    transform: Transform::from_xyz(
+                        0.0,
+                        0.0,
+                        aseprite.layer_id(layer.layer) as f32 * LAYER_Z_STEP,
+                    ),
+                    ..Default::default()
+                })
+                .id();
+            children.push(child);
+        }
+        commands.entity(entity).push_children(&children);
+    }
+}
+
+pub fn fixup_aseprite_equipment(
+    mut commands: Commands,
+    mut query: Query<
+        (Entity, &AsepriteEquipment, &mut AsepriteEquipmentChildren),
+        Changed<AsepriteEquipment>,
+    >,
+    mut items: Query<(&mut Handle<Aseprite>, &mut Transform), With<AsepriteEquipmentItem>>,
+) {
+    for (entity, equipment, mut children) in query.iter_mut() {
+        children.retain(|name, child| {
+            let keep = equipment.slot(name).is_some();
+            if !keep {
+                commands.entity(*child).despawn_recursive();
+            }
+            keep
+        });
+        for (i, slot) in equipment.slots.iter().enumerate() {
+            let z = (i + 1) as f32 * LAYER_Z_STEP;
+            if let Some(child) = children.get(slot.name) {
+                if let Ok((mut aseprite_handle, mut transform)) = items.get_mut(*child) {
+                    if *aseprite_handle != slot.aseprite {
+                        *aseprite_handle = slot.aseprite.clone();
+                    }
+                    transform.translation.z = z;
+                }
+                continue;
+            }
+            let child = commands
+                .spawn((
+                    slot.aseprite.clone(),
+                    SpriteSheetBundle {
+                        transform: Transform::from_xyz(0.0, 0.0, z),
+                        ..Default::default()
+                    },
+                    AsepriteEquipmentItem { slot: slot.name },
+                ))
+                .id();
+            commands.entity(entity).add_child(child);
+            children.insert(slot.name, child);
+        }
+    }
+}
+
+pub fn sync_aseprite_equipment(
+    aseprites: Res<Assets<Aseprite>>,
+    drivers: Query<
+        (
+            &AsepriteAnimation,
+            &TextureAtlasSprite,
+            &AsepriteEquipmentChildren,
+        ),
+        Without<AsepriteEquipmentItem>,
+    >,
+    mut items: Query<
+        (
+            &Handle<Aseprite>,
+            &mut Handle<TextureAtlas>,
+            &mut TextureAtlasSprite,
+        ),
+        With<AsepriteEquipmentItem>,
+    >,
+) {
+    for (ase_anim, driver_sprite, children) in drivers.iter() {
+        let frame = ase_anim.current_frame();
+        for child in children.values() {
+            let (aseprite_handle, mut texture_atlas, mut sprite) =
+                coalesce!(items.get_mut(*child).ok(), continue);
+            let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+            if *texture_atlas != aseprite.atlas {
+                *texture_atlas = aseprite.atlas.clone();
+            }
+            let index = coalesce!(aseprite.frame_index(frame), continue) as usize;
+            if sprite.index != index {
+                sprite.index = index;
+            }
+            if sprite.flip_x != driver_sprite.flip_x {
+                sprite.flip_x = driver_sprite.flip_x;
+            }
+            if sprite.flip_y != driver_sprite.flip_y {
+                sprite.flip_y = driver_sprite.flip_y;
+            }
+            if sprite.anchor.as_vec() != driver_sprite.anchor.as_vec() {
+                sprite.anchor = driver_sprite.anchor.clone();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{Slice, SliceSegment};
+    use crate::components::AsepriteNinepatchBundle;
+    use crate::test_utils::{asset_app, empty_aseprite, load_aseprite};
+    use bevy::time::TimeUpdateStrategy;
+    use std::ops::Range;
+    use std::time::Duration;
+
+    // `fixup_texture_atlas` and the sprite animation systems, with every update 100ms apart.
+    fn animation_app() -> App {
+        let mut app = asset_app();
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )))
+        .add_systems(PreUpdate, fixup_texture_atlas)
+        .add_systems(
+            PreUpdate,
+            fixup_aseprite_animation.after(fixup_texture_atlas),
+        )
+        .add_systems(Update, animate_aseprite);
+        app
+    }
+
+    #[test]
+    fn ninepatch_spawned_before_load_plays_its_tag() {
+        let mut app = animation_app();
+        let handle = app
+            .world
+            .resource::<AssetServer>()
+            .load("characters.aseprite");
+        let entity = app
+            .world
+            .spawn(AsepriteNinepatchBundle {
+                aseprite: handle,
+                aseprite_atlas: AsepriteAtlas {
+                    tag: Some("Walk"),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .id();
+        load_aseprite(&mut app, "characters.aseprite");
+        for _ in 0..3 {
+            app.update();
+        }
+
+        let animation = app.world.get::<AsepriteAnimation>(entity).unwrap();
+        assert_eq!(animation.frame_range, 1..9);
+        assert!(animation.current_frame() > 1);
+    }
+
+    fn slice(name: &str, segments: Vec<SliceSegment>) -> Slice {
+        Slice {
+            name: name.to_owned(),
+            user_data: None,
+            segments,
+        }
+    }
+
+    fn key(from_frame: usize, origin: Vec2, size: Vec2, pivot: Option<Vec2>) -> SliceSegment {
+        SliceSegment {
+            from_frame,
+            origin,
+            size,
+            pivot,
+            ninepatch_center: None,
+        }
+    }
+
+    // A headless app holding a 16x16, four-frame asset with the given slices.
+    fn canvas_app(slices: Vec<Slice>) -> (App, Handle<Aseprite>) {
+        let mut aseprite = empty_aseprite(4);
+        aseprite.size = Vec2::splat(16.0);
+        aseprite.slices = slices;
+        let mut app = asset_app();
+        let handle = app.world.resource_mut::<Assets<Aseprite>>().add(aseprite);
+        (app, handle)
+    }
+
+    fn animation_at(frame_range: Range<u32>, frame: u32) -> AsepriteAnimation {
+        AsepriteAnimation {
+            current_index: frame,
+            index_range: frame_range.clone(),
+            frame_range,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn root_motion_follows_flip_and_scale_and_resets_on_tag_switch() {
+        let point = Vec2::new(1.0, 1.0);
+        let size = Vec2::new(2.0, 2.0);
+        let (mut app, handle) = canvas_app(vec![slice(
+            "Root",
+            vec![
+                key(0, Vec2::new(4.0, 8.0), size, Some(point)),
+                key(1, Vec2::new(6.0, 8.0), size, Some(point)),
+                key(2, Vec2::new(9.0, 6.0), size, Some(point)),
+                key(3, Vec2::new(9.0, 4.0), size, Some(point)),
+            ],
+        )]);
+        app.add_systems(Update, update_aseprite_root_motion);
+        // Drawn flipped at twice the canvas size, with the entity squashed vertically.
+        let entity = app
+            .world
+            .spawn((
+                handle,
+                AsepriteAtlas::default(),
+                animation_at(0..4, 0),
+                AsepriteRootMotion {
+                    slice: "Root",
+                    apply: true,
+                    ..Default::default()
+                },
+                TextureAtlasSprite {
+                    flip_x: true,
+                    custom_size: Some(Vec2::splat(32.0)),
+                    ..Default::default()
+                },
+                Transform::from_scale(Vec3::new(1.0, 0.5, 1.0)),
+            ))
+            .id();
+        let root_motion = |app: &App| {
+            let delta = app.world.get::<AsepriteRootMotion>(entity).unwrap().delta;
+            let translation = app.world.get::<Transform>(entity).unwrap().translation;
+            (delta, translation.truncate())
+        };
+
+        // The first frame anchors the sprite at the pivot (5, 9) without moving.
+        app.update();
+        assert_eq!(root_motion(&app), (Vec2::ZERO, Vec2::ZERO));
+        let anchor = app
+            .world
+            .get::<TextureAtlasSprite>(entity)
+            .unwrap()
+            .anchor
+            .as_vec();
+        assert_eq!(anchor, Vec2::new(11.0 / 16.0 - 0.5, 0.5 - 9.0 / 16.0));
+
+        // The pivot moves 2 canvas pixels right, drawn as 4 and mirrored.
+        app.world
+            .get_mut::<AsepriteAnimation>(entity)
+            .unwrap()
+            .current_index = 1;
+        app.update();
+        let moved = Vec2::new(-4.0, 0.0);
+        assert_eq!(root_motion(&app), (moved, moved));
+
+        // Switching to a tag over frames 2..4 starts over from its first pivot.
+        *app.world.get_mut::<AsepriteAnimation>(entity).unwrap() = animation_at(2..4, 2);
+        app.update();
+        assert_eq!(root_motion(&app), (Vec2::ZERO, moved));
+
+        // The pivot moves 2 canvas pixels up, drawn as 4 and halved by the entity scale.
+        app.world
+            .get_mut::<AsepriteAnimation>(entity)
+            .unwrap()
+            .current_index = 3;
+        app.update();
+        let up = Vec2::new(0.0, 2.0);
+        assert_eq!(root_motion(&app), (up, moved + up));
+    }
+
+    #[test]
+    fn canvas_to_local_follows_flip_anchor_and_size() {
+        let region = Rect::new(0.0, 0.0, 16.0, 16.0);
+        let corner = Vec2::ZERO;
+        let sprite = TextureAtlasSprite::default();
+        assert_eq!(
+            canvas_to_local(corner, region, &sprite),
+            Vec2::new(-8.0, 8.0)
+        );
+        let flipped = TextureAtlasSprite {
+            flip_x: true,
+            flip_y: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            canvas_to_local(corner, region, &flipped),
+            Vec2::new(8.0, -8.0)
+        );
+        let anchored = TextureAtlasSprite {
+            anchor: Anchor::BottomLeft,
+            ..Default::default()
+        };
+        assert_eq!(
+            canvas_to_local(corner, region, &anchored),
+            Vec2::new(0.0, 16.0)
+        );
+        let stretched = TextureAtlasSprite {
+            custom_size: Some(Vec2::new(32.0, 8.0)),
+            ..Default::default()
+        };
+        assert_eq!(
+            canvas_to_local(Vec2::new(4.0, 4.0), region, &stretched),
+            Vec2::new(-8.0, 2.0)
+        );
+        // Slice regions are relative to the slice, not the canvas.
+        let slice_region = Rect::new(4.0, 4.0, 12.0, 12.0);
+        assert_eq!(
+            canvas_to_local(Vec2::new(4.0, 4.0), slice_region, &sprite),
+            Vec2::new(-4.0, 4.0)
+        );
+    }
+
+    #[test]
+    fn pivot_anchor_follows_flip() {
+        let region = Rect::new(0.0, 0.0, 16.0, 16.0);
+        let point = Vec2::new(4.0, 12.0);
+        let anchor = pivot_anchor(point, region, &TextureAtlasSprite::default());
+        assert_eq!(anchor.as_vec(), Vec2::new(-0.25, -0.25));
+        let flipped = TextureAtlasSprite {
+            flip_y: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            pivot_anchor(point, region, &flipped).as_vec(),
+            Vec2::new(-0.25, 0.25)
+        );
+        // The pivot's local position is the origin once anchored there.
+        let anchored = TextureAtlasSprite {
+            anchor,
+            ..Default::default()
+        };
+        assert_eq!(canvas_to_local(point, region, &anchored), Vec2::ZERO);
+    }
+
+    #[test]
+    fn local_to_world_bounds_the_transformed_rect() {
+        let rect = Rect::new(-1.0, -2.0, 3.0, 4.0);
+        let scaled = GlobalTransform::from(
+            Transform::from_xyz(10.0, 0.0, 0.0).with_scale(Vec3::new(2.0, 2.0, 1.0)),
+        );
+        assert_eq!(
+            local_to_world(rect, &scaled),
+            Rect::new(8.0, -4.0, 16.0, 8.0)
+        );
+        let rotated = GlobalTransform::from(Transform::from_rotation(Quat::from_rotation_z(
+            std::f32::consts::FRAC_PI_2,
+        )));
+        let world = local_to_world(rect, &rotated);
+        assert!(world.min.abs_diff_eq(Vec2::new(-4.0, -1.0), 1e-5));
+        assert!(world.max.abs_diff_eq(Vec2::new(2.0, 3.0), 1e-5));
+    }
+
+    #[test]
+    fn hitboxes_follow_flip_and_transform() {
+        let (mut app, handle) = canvas_app(vec![slice(
+            "Hit",
+            vec![key(0, Vec2::ZERO, Vec2::new(4.0, 2.0), None)],
+        )]);
+        app.add_systems(Update, update_aseprite_hitboxes);
+        let entity = app
+            .world
+            .spawn((
+                handle,
+                AsepriteAtlas::default(),
+                AsepriteAnimation::default(),
+                TextureAtlasSprite {
+                    flip_x: true,
+                    ..Default::default()
+                },
+                GlobalTransform::from_xyz(100.0, 0.0, 0.0),
+                AsepriteHitboxes {
+                    slices: vec!["Hit", "Missing"],
+                    ..Default::default()
+                },
+            ))
+            .id();
+        app.update();
+
+        // The top-left corner of the canvas lands at the top right once flipped.
+        let hitboxes = &app.world.get::<AsepriteHitboxes>(entity).unwrap().hitboxes;
+        assert_eq!(
+            hitboxes,
+            &[AsepriteHitbox {
+                slice: "Hit",
+                local: Rect::new(4.0, 6.0, 8.0, 8.0),
+                world: Rect::new(104.0, 6.0, 108.0, 8.0),
+            }]
+        );
+    }
+
+    #[test]
+    fn sockets_follow_flipped_parents() {
+        let (mut app, handle) = canvas_app(vec![slice(
+            "Hand",
+            vec![key(
+                0,
+                Vec2::new(10.0, 4.0),
+                Vec2::new(4.0, 4.0),
+                Some(Vec2::new(1.0, 3.0)),
+            )],
+        )]);
+        app.add_systems(Update, update_aseprite_sockets);
+        let mut at_pivot = None;
+        let mut at_center = None;
+        app.world
+            .spawn((
+                handle,
+                AsepriteAtlas::default(),
+                AsepriteAnimation::default(),
+                TextureAtlasSprite {
+                    flip_x: true,
+                    ..Default::default()
+                },
+            ))
+            .with_children(|parent| {
+                let socket = AsepriteSocket {
+                    slice: "Hand",
+                    pivot: true,
+                    mirror: true,
+                };
+                at_pivot = Some(parent.spawn((socket, Transform::default())).id());
+                let socket = AsepriteSocket {
+                    slice: "Hand",
+                    pivot: false,
+                    mirror: false,
+                };
+                at_center = Some(parent.spawn((socket, Transform::default())).id());
+            });
+        app.update();
+
+        // The pivot (11, 7) and the center (12, 6) are mirrored across the canvas center.
+        let transform = app.world.get::<Transform>(at_pivot.unwrap()).unwrap();
+        assert_eq!(transform.translation.truncate(), Vec2::new(-3.0, 1.0));
+        assert_eq!(transform.scale, Vec3::new(-1.0, 1.0, 1.0));
+        let transform = app.world.get::<Transform>(at_center.unwrap()).unwrap();
+        assert_eq!(transform.translation.truncate(), Vec2::new(-4.0, 2.0));
+        assert_eq!(transform.scale, Vec3::ONE);
+    }
+}
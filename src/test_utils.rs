@@ -0,0 +1,99 @@
+use crate::assets::{Aseprite, AsepriteLoader, AtlasKey};
+use asefile::AsepriteFile;
+use bevy::prelude::*;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Reads a file from `assets/` with `asefile`.
+pub(crate) fn read_asefile(path: &str) -> AsepriteFile {
+    AsepriteFile::read_file(
+        &Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(path),
+    )
+    .unwrap()
+}
+
+/// A headless app that can load Aseprite files from `assets/`.
+pub(crate) fn asset_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+        .add_asset::<Image>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<Aseprite>()
+        .add_asset_loader(AsepriteLoader::default());
+    app
+}
+
+pub(crate) fn load_aseprite(app: &mut App, path: &str) -> Handle<Aseprite> {
+    let handle = app.world.resource::<AssetServer>().load(path);
+    for _ in 0..1000 {
+        app.update();
+        if app.world.resource::<Assets<Aseprite>>().contains(&handle) {
+            return handle;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    panic!("`{}` did not load", path);
+}
+
+/// Pixels of the full-frame atlas entry of `frame`.
+pub(crate) fn frame_image(app: &App, aseprite: &Aseprite, frame: u32) -> RgbaImage {
+    let atlas = app
+        .world
+        .resource::<Assets<TextureAtlas>>()
+        .get(&aseprite.atlas)
+        .unwrap();
+    let texture = app
+        .world
+        .resource::<Assets<Image>>()
+        .get(&atlas.texture)
+        .unwrap();
+    let buffer = RgbaImage::from_raw(
+        texture.texture_descriptor.size.width,
+        texture.texture_descriptor.size.height,
+        texture.data.clone(),
+    )
+    .unwrap();
+    let index = aseprite.atlas_indexes[&AtlasKey {
+        layer: None,
+        frame,
+        slice: None,
+        ninepatch: None,
+    }];
+    let rect = atlas.textures[index as usize];
+    image::imageops::crop_imm(
+        &buffer,
+        rect.min.x as u32,
+        rect.min.y as u32,
+        rect.width() as u32,
+        rect.height() as u32,
+    )
+    .to_image()
+}
+
+/// An asset with `num_frames` frames of 100ms and no layers, tags or slices.
+pub(crate) fn empty_aseprite(num_frames: u32) -> Aseprite {
+    Aseprite {
+        path: PathBuf::new(),
+        size: Vec2::ZERO,
+        layers: Vec::new(),
+        layer_blend_modes: Vec::new(),
+        layer_opacities: Vec::new(),
+        layer_parents: Vec::new(),
+        layer_in_frames: Vec::new(),
+        tags: Vec::new(),
+        slices: Vec::new(),
+        frame_durations: vec![Duration::from_millis(100); num_frames as usize],
+        num_frames,
+        atlas_indexes: HashMap::new(),
+        atlas: Handle::default(),
+        alpha_masks: Vec::new(),
+        alpha_threshold: 0,
+        outlines: HashMap::new(),
+        tilesets: Vec::new(),
+        tilemaps: Vec::new(),
+    }
+}
@@ -0,0 +1,33 @@
+use crate::assets::Aseprite;
+use bevy::prelude::*;
+
+/// Selects the tilemap layer to spawn and the frame it shows. Insert an `AsepriteAnimation`
+/// next to it to play `tag` (or every frame when `tag` is `None`).
+#[derive(Component, Clone, Default, Eq, PartialEq, Debug)]
+pub struct AsepriteTilemapLayer {
+    pub layer: &'static str,
+    pub tag: Option<&'static str>,
+    pub frame: u32,
+}
+
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct AsepriteTilemapChildren(pub Vec<Entity>);
+
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct AsepriteTile {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Spawns one sprite per cell of a tilemap layer, centered on the entity's transform.
+#[derive(Bundle, Default)]
+pub struct AsepriteTilemapBundle {
+    pub aseprite: Handle<Aseprite>,
+    pub tilemap: AsepriteTilemapLayer,
+    pub children: AsepriteTilemapChildren,
+
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub computed_visibility: ComputedVisibility,
+}
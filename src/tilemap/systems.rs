@@ -0,0 +1,173 @@
+use crate::assets::{Aseprite, AsepriteTileset, TilemapTile};
+use crate::components::AsepriteAnimation;
+use crate::tilemap::components::{AsepriteTile, AsepriteTilemapChildren, AsepriteTilemapLayer};
+use crate::user_data::AsepriteTileUserData;
+use crate::utils::coalesce;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+pub(crate) fn apply_tile(
+    tile: TilemapTile,
+    sprite: &mut TextureAtlasSprite,
+    visibility: &mut Visibility,
+    transform: &mut Transform,
+) {
+    if tile.is_empty() {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    sprite.index = tile.id as usize;
+    sprite.flip_x = tile.flip_x;
+    sprite.flip_y = tile.flip_y;
+    transform.rotation = if tile.rotate_90cw {
+        Quat::from_rotation_z(-FRAC_PI_2)
+    } else {
+        Quat::IDENTITY
+    };
+}
+
+fn apply_tile_user_data(
+    commands: &mut Commands,
+    entity: Entity,
+    tile: TilemapTile,
+    tileset: &AsepriteTileset,
+) {
+    match tileset.tile_user_data(tile.id) {
+        Some(user_data) if !tile.is_empty() => {
+            commands.entity(entity).insert(user_data.clone());
+        }
+        _ => {
+            commands.entity(entity).remove::<AsepriteTileUserData>();
+        }
+    }
+}
+
+pub fn fixup_aseprite_tilemap(
+    mut commands: Commands,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        Entity,
+        &Handle<Aseprite>,
+        Ref<AsepriteTilemapLayer>,
+        &mut AsepriteTilemapChildren,
+    )>,
+    mut tiles: Query<
+        (
+            Entity,
+            &AsepriteTile,
+            &mut TextureAtlasSprite,
+            &mut Visibility,
+            &mut Transform,
+        ),
+        Without<AsepriteTilemapLayer>,
+    >,
+) {
+    for (entity, aseprite_handle, tilemap_layer, mut children) in query.iter_mut() {
+        if !tilemap_layer.is_changed() && !children.is_empty() {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let tilemap = aseprite.tilemap(tilemap_layer.layer);
+        let tileset = aseprite.tileset(tilemap.tileset);
+        if children.len() == (tilemap.size.x * tilemap.size.y) as usize {
+            let mut iter = tiles.iter_many_mut(children.iter());
+            while let Some((child, tile, mut sprite, mut visibility, mut transform)) =
+                iter.fetch_next()
+            {
+                let tile = tilemap.tile(tilemap_layer.frame, tile.x, tile.y);
+                apply_tile(tile, &mut sprite, &mut visibility, &mut transform);
+                apply_tile_user_data(&mut commands, child, tile, tileset);
+            }
+            continue;
+        }
+        for child in children.drain(..) {
+            commands.entity(child).despawn_recursive();
+        }
+        let tile_size = tileset.tile_size.as_vec2();
+        let origin = Vec2::new(-0.5, 0.5) * (tilemap.size.as_vec2() - Vec2::ONE) * tile_size;
+        for y in 0..tilemap.size.y {
+            for x in 0..tilemap.size.x {
+                let mut bundle = SpriteSheetBundle {
+                    texture_atlas: tileset.atlas.clone(),
+                    transform: Transform::from_translation(
+                        (origin + Vec2::new(x as f32, -(y as f32)) * tile_size).extend(0.0),
+                    ),
+                    ..Default::default()
+                };
+                let tile = tilemap.tile(tilemap_layer.frame, x, y);
+                apply_tile(
+                    tile,
+                    &mut bundle.sprite,
+                    &mut bundle.visibility,
+                    &mut bundle.transform,
+                );
+                let child = commands.spawn((bundle, AsepriteTile { x, y })).id();
+                apply_tile_user_data(&mut commands, child, tile, tileset);
+                children.push(child);
+            }
+        }
+        commands.entity(entity).push_children(&children);
+    }
+}
+
+pub fn fixup_aseprite_tilemap_animation(
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        &Handle<Aseprite>,
+        &mut AsepriteTilemapLayer,
+        &mut AsepriteAnimation,
+    )>,
+) {
+    for (aseprite_handle, mut tilemap_layer, mut ase_anim) in query.iter_mut() {
+        if !tilemap_layer.is_changed() && !ase_anim.index_range.is_empty() {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.fixup_frames(tilemap_layer.tag, aseprite);
+        if tilemap_layer.frame != frame {
+            tilemap_layer.frame = frame;
+        }
+    }
+}
+
+pub fn animate_aseprite_tilemap(
+    mut commands: Commands,
+    time: Res<Time>,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<(
+        &Handle<Aseprite>,
+        &mut AsepriteTilemapLayer,
+        &mut AsepriteAnimation,
+        &AsepriteTilemapChildren,
+    )>,
+    mut tiles: Query<
+        (&mut TextureAtlasSprite, &mut Visibility, &mut Transform),
+        (With<AsepriteTile>, Without<AsepriteTilemapLayer>),
+    >,
+) {
+    for (aseprite_handle, mut tilemap_layer, mut ase_anim, children) in query.iter_mut() {
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let frame = ase_anim.step(time.delta(), aseprite) as u32;
+        if frame == tilemap_layer.frame {
+            continue;
+        }
+        let tilemap = aseprite.tilemap(tilemap_layer.layer);
+        let tileset = aseprite.tileset(tilemap.tileset);
+        if children.len() == (tilemap.size.x * tilemap.size.y) as usize {
+            for (i, child) in children.iter().enumerate() {
+                let (x, y) = (i as u32 % tilemap.size.x, i as u32 / tilemap.size.x);
+                let tile = tilemap.tile(frame, x, y);
+                if tile == tilemap.tile(tilemap_layer.frame, x, y) {
+                    continue;
+                }
+                if let Ok((mut sprite, mut visibility, mut transform)) = tiles.get_mut(*child) {
+                    apply_tile(tile, &mut sprite, &mut visibility, &mut transform);
+                    apply_tile_user_data(&mut commands, *child, tile, tileset);
+                }
+            }
+        }
+        // The tiles are already up to date, so skip the full refresh in `fixup_aseprite_tilemap`.
+        tilemap_layer.bypass_change_detection().frame = frame;
+    }
+}
@@ -1,11 +1,9 @@
 use crate::assets::Aseprite;
+use crate::components::{AsepriteAnimation, AsepriteAtlas};
+use crate::ninepatch::NinepatchFill;
 use bevy::prelude::*;
-use bevy::ui::{ContentSize, FocusPolicy, widget::UiImageSize};
-use std::ops::Range;
+use bevy::ui::{widget::UiImageSize, ContentSize, FocusPolicy};
 use std::time::Duration;
-use crate::components::{AsepriteAnimation, AsepriteAtlas, AsepriteUiBundle, AsepriteUiChildren, AsepriteUiChild, AsepriteUiChildBundle, AsepriteUiNinepatch};
-
-
 
 #[derive(Component, Default)]
 pub enum AsepriteUiChildren {
@@ -15,13 +13,30 @@ pub enum AsepriteUiChildren {
     Ninepatches([Entity; 9]),
 }
 
+impl AsepriteUiChildren {
+    pub fn entities(&self) -> &[Entity] {
+        match self {
+            AsepriteUiChildren::NoChild => &[],
+            AsepriteUiChildren::Sprite(entity) => std::slice::from_ref(entity),
+            AsepriteUiChildren::Ninepatches(entities) => entities,
+        }
+    }
+}
+
 #[derive(Component, Default)]
 pub struct AsepriteUiChild;
 
-
 #[derive(Component, Default, Deref, Clone, Copy)]
 pub struct AsepriteUiNinepatch(pub u8);
 
+/// A clipping container that repeats one ninepatch piece at `tile_size` to fill itself.
+#[derive(Component, Default, Clone, Copy)]
+pub struct AsepriteUiTiledPiece {
+    pub tile_size: Vec2,
+    pub tiles: usize,
+    pub columns: usize,
+}
+
 #[derive(Bundle, Default)]
 pub struct AsepriteUiChildBundle {
     pub aseprite: Handle<Aseprite>,
@@ -47,6 +62,8 @@ pub struct AsepriteUiChildBundle {
     pub border_color: BorderColor,
 }
 
+/// A UI node showing an Aseprite sprite, or a ninepatch laid out over the node when
+/// `AsepriteAtlas::slice` has ninepatch data.
 #[derive(Bundle, Default)]
 pub struct AsepriteUiBundle {
     pub aseprite: Handle<Aseprite>,
@@ -63,4 +80,77 @@ pub struct AsepriteUiBundle {
     pub z_index: ZIndex,
 
     pub children: AsepriteUiChildren,
+    pub ninepatch_fill: NinepatchFill,
+    pub layout: AsepriteUiLayout,
+}
+
+/// Derives the node's `Style` from its ninepatch slice: with `padding`, the ninepatch borders
+/// become the padding so content stays inside the center, and with `min_size`, the minimum size
+/// is set so the node never shrinks below its corners.
+#[derive(Component, Clone, Copy, Default, Debug, Eq, PartialEq)]
+pub struct AsepriteUiLayout {
+    pub padding: bool,
+    pub min_size: bool,
+}
+
+/// The tag and slice an `AsepriteUiButton` shows in one state.
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq)]
+pub struct AsepriteUiButtonStyle {
+    pub tag: Option<&'static str>,
+    pub slice: Option<&'static str>,
+}
+
+/// Switches the node's `AsepriteAtlas` tag and slice with its `Interaction`. States left at
+/// their default fall back to `normal`. When `press_animation` is set, that tag is played once
+/// on press before the state's style is shown again.
+#[derive(Component, Clone, Default, Debug)]
+pub struct AsepriteUiButton {
+    pub normal: AsepriteUiButtonStyle,
+    pub hovered: AsepriteUiButtonStyle,
+    pub pressed: AsepriteUiButtonStyle,
+    pub disabled: AsepriteUiButtonStyle,
+    pub press_animation: Option<&'static str>,
+    pub press_remaining: Duration,
+}
+
+impl AsepriteUiButton {
+    pub fn style(&self, interaction: Interaction, disabled: bool) -> AsepriteUiButtonStyle {
+        let style = if disabled {
+            self.disabled
+        } else {
+            match interaction {
+                Interaction::Pressed => self.pressed,
+                Interaction::Hovered => self.hovered,
+                Interaction::None => self.normal,
+            }
+        };
+        if style == AsepriteUiButtonStyle::default() {
+            self.normal
+        } else {
+            style
+        }
+    }
+}
+
+/// Shows the `disabled` style of an `AsepriteUiButton` and ignores its interaction.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct AsepriteUiDisabled;
+
+#[derive(Bundle, Default)]
+pub struct AsepriteUiButtonBundle {
+    pub ui: AsepriteUiBundle,
+    pub button: AsepriteUiButton,
+    pub interaction: Interaction,
+}
+
+/// Integer factor applied to UI ninepatch borders. Each source pixel is drawn over a whole
+/// number of physical pixels, the closest to this factor times the UI and window scales. As a
+/// resource it is the global default; as a component it overrides the default for one node.
+#[derive(Resource, Component, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AsepriteUiPixelScale(pub u32);
+
+impl Default for AsepriteUiPixelScale {
+    fn default() -> Self {
+        Self(1)
+    }
 }
@@ -0,0 +1,4 @@
+mod components;
+pub(crate) mod systems;
+
+pub use components::*;
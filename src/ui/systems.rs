@@ -1,8 +1,56 @@
-use crate::assets::Aseprite;
-use crate::components::{AsepriteAnimation, AsepriteAtlas, AsepriteUiBundle, AsepriteUiChildren, AsepriteUiChild, AsepriteUiChildBundle, AsepriteUiNinepatch};
-use crate::ui::components::{AsepriteUiBundle, AsepriteUiChildren, AsepriteUiChild, AsepriteUiChildBundle, AsepriteUiNinepatch};
+use crate::assets::{Aseprite, SliceSegment};
+use crate::components::{AsepriteAnimation, AsepriteAtlas};
+use crate::ninepatch::NinepatchFill;
+use crate::ui::components::{
+    AsepriteUiButton, AsepriteUiChild, AsepriteUiChildBundle, AsepriteUiChildren,
+    AsepriteUiDisabled, AsepriteUiLayout, AsepriteUiPixelScale, AsepriteUiTiledPiece,
+};
+use crate::utils::coalesce;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::ui::widget::UiImageSize;
+use bevy::window::PrimaryWindow;
+
+/// Scales ninepatch borders by the node's or the global `AsepriteUiPixelScale` and snaps them to
+/// whole physical pixels.
+#[derive(SystemParam)]
+pub struct NinepatchUiSnap<'w, 's> {
+    pixel_scale: Res<'w, AsepriteUiPixelScale>,
+    ui_scale: Res<'w, UiScale>,
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+impl<'w, 's> NinepatchUiSnap<'w, 's> {
+    /// Returns the scaled slice size and ninepatch center.
+    fn geometry(
+        &self,
+        node_scale: Option<&AsepriteUiPixelScale>,
+        slice: &SliceSegment,
+        center: Rect,
+    ) -> (Vec2, Rect) {
+        let factor = node_scale
+            .map_or(self.pixel_scale.0, |scale| scale.0)
+            .max(1) as f32;
+        let window_scale = self
+            .windows
+            .get_single()
+            .map_or(1.0, |window| window.scale_factor());
+        let physical = (self.ui_scale.scale * window_scale) as f32;
+        // A whole number of physical pixels per source pixel keeps every border, and therefore
+        // the sum of the borders, on the physical pixel grid.
+        let pixel = (factor * physical).round().max(1.0);
+        let snap = |value: f32| value * pixel / physical;
+        let min = Vec2::new(snap(center.min.x), snap(center.min.y));
+        let center_size = Vec2::new(snap(center.width()), snap(center.height()));
+        let max_border = Vec2::new(
+            snap(slice.size.x - center.max.x),
+            snap(slice.size.y - center.max.y),
+        );
+        (
+            min + center_size + max_border,
+            Rect::from_corners(min, min + center_size),
+        )
+    }
+}
 
 pub fn fixup_aseprite_animation_ui(
     aseprites: Res<Assets<Aseprite>>,
@@ -11,16 +59,20 @@ pub fn fixup_aseprite_animation_ui(
             &Handle<Aseprite>,
             &AsepriteAtlas,
             &mut AsepriteAnimation,
-            &mut UiTextureAtlasImage,
+            Option<&mut UiTextureAtlasImage>,
+        ),
+        (
+            Changed<AsepriteAtlas>,
+            Or<(With<UiTextureAtlasImage>, With<AsepriteUiChildren>)>,
         ),
-        Changed<AsepriteAtlas>,
     >,
 ) {
-    for (aseprite_handle, ase_atlas, mut ase_anim, mut sprite) in query.iter_mut() {
+    for (aseprite_handle, ase_atlas, mut ase_anim, sprite) in query.iter_mut() {
         if let Some(aseprite) = aseprites.get(aseprite_handle) {
-            sprite.index = ase_anim.fixup(ase_atlas, aseprite);
-            info!("fixup index: {}", sprite.index);
-            info!("fixup atlas: {:?}", ase_atlas);
+            let index = ase_anim.fixup(ase_atlas, aseprite);
+            if let Some(mut sprite) = sprite {
+                sprite.index = index;
+            }
         } else {
             error!("Fail to load aseprite from handle");
         }
@@ -30,17 +82,22 @@ pub fn fixup_aseprite_animation_ui(
 pub fn animate_aseprite_ui(
     time: Res<Time>,
     aseprites: Res<Assets<Aseprite>>,
-    mut query: Query<(
-        &Handle<Aseprite>,
-        &mut AsepriteAnimation,
-        &mut UiTextureAtlasImage,
-    )>,
+    mut query: Query<
+        (
+            &Handle<Aseprite>,
+            &mut AsepriteAnimation,
+            Option<&mut UiTextureAtlasImage>,
+        ),
+        Or<(With<UiTextureAtlasImage>, With<AsepriteUiChildren>)>,
+    >,
 ) {
-    for (aseprite_handle, mut ase_anim, mut sprite) in query.iter_mut() {
+    for (aseprite_handle, mut ase_anim, sprite) in query.iter_mut() {
         if let Some(aseprite) = aseprites.get(aseprite_handle) {
             let next_index = ase_anim.step(time.delta(), aseprite);
-            if sprite.index != next_index {
-                sprite.index = next_index;
+            if let Some(mut sprite) = sprite {
+                if sprite.index != next_index {
+                    sprite.index = next_index;
+                }
             }
         }
     }
@@ -49,78 +106,731 @@ pub fn animate_aseprite_ui(
 pub fn fixup_ninepatch_ui(
     mut commands: Commands,
     aseprites: Res<Assets<Aseprite>>,
-    mut query: Query<(
-        Entity,
-        &mut AsepriteUiChildren,
-        &AsepriteAtlas,
-        &Handle<Aseprite>,
-    ), (Changed<AsepriteAtlas>, Without<AsepriteUiChild>)>,
+    mut query: Query<
+        (
+            Entity,
+            &mut AsepriteUiChildren,
+            Ref<AsepriteAtlas>,
+            &AsepriteAnimation,
+            Ref<Handle<Aseprite>>,
+            Ref<NinepatchFill>,
+            Option<&AsepriteUiPixelScale>,
+        ),
+        Without<AsepriteUiChild>,
+    >,
     mut children_query: Query<&mut AsepriteAtlas, With<AsepriteUiChild>>,
+    snap: NinepatchUiSnap,
 ) {
-    for (entity, mut children, atlas, aseprite_handle) in query.iter_mut() {
-        *children = match *children {
-            AsepriteUiChildren::NoChild => {
-                let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
-                if let Some(slice_name) = atlas.slice 
-                && let slice = aseprite.slice(slice_name, 0)
-                && let Some(ninepatch_center) = slice.ninepatch_center {
-                    let mut ninepatches = [entity; 9];
-                    for i in 0u8..9 {
-                        ninepatches[i as usize] = commands.spawn(AsepriteUiChildBundle {
-                            style: Style{
-                                position_type: PositionType::Absolute,
-                                top: match i / 3 { 0 => Val::Px(0.0), 1 => Val::Px(ninepatch_center.min.y), _ => Val::Auto },
-                                bottom: match i / 3 { 0 => Val::Auto, 1 => Val::Px(slice.size.y - ninepatch_center.max.y), _ => Val::Px(0.0) },
-                                left: match i % 3 { 0 => Val::Px(0.0), 1 => Val::Px(ninepatch_center.min.x), _ => Val::Auto },
-                                right: match i % 3 { 0 => Val::Auto, 1 => Val::Px(slice.size.x - ninepatch_center.max.x), _ => Val::Px(0.0) },
-                                height: match i / 3 { 0 => Val::Px(ninepatch_center.min.y), 1 => Val::Auto, _ => Val::Px(slice.size.y - ninepatch_center.max.y) },
-                                width: match i % 3 { 0 => Val::Px(ninepatch_center.min.x), 1 => Val::Auto, _ => Val::Px(slice.size.x - ninepatch_center.max.x) },
-                                ..Default::default()
+    for (entity, mut children, atlas, ase_anim, aseprite_handle, fill, pixel_scale) in
+        query.iter_mut()
+    {
+        let has_children = !children.entities().is_empty();
+        if has_children
+            && !atlas.is_changed()
+            && !aseprite_handle.is_changed()
+            && !fill.is_changed()
+        {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(&*aseprite_handle), continue);
+        let frame = ase_anim.current_frame() as usize;
+        let ninepatch = atlas.slice.map_or(false, |slice_name| {
+            aseprite.slice(slice_name, frame).ninepatch_center.is_some()
+        });
+        let reusable = match *children {
+            AsepriteUiChildren::NoChild => false,
+            AsepriteUiChildren::Sprite(_) => !ninepatch && !aseprite_handle.is_changed(),
+            AsepriteUiChildren::Ninepatches(_) => {
+                ninepatch && !aseprite_handle.is_changed() && !fill.is_changed()
+            }
+        };
+        if reusable {
+            // Slice geometry is re-laid out by `layout_ninepatch_ui` once the animation resets.
+            let mut iter = children_query.iter_many_mut(children.entities());
+            while let Some(mut child_atlas) = iter.fetch_next() {
+                if child_atlas.tag != atlas.tag {
+                    child_atlas.tag = atlas.tag;
+                }
+                if child_atlas.slice != atlas.slice {
+                    child_atlas.slice = atlas.slice;
+                }
+                if child_atlas.layer != atlas.layer {
+                    child_atlas.layer = atlas.layer;
+                }
+            }
+            continue;
+        }
+        for child in children.entities() {
+            commands.entity(*child).despawn_recursive();
+        }
+        *children = spawn_ui_children(
+            &mut commands,
+            entity,
+            &atlas,
+            &aseprite_handle,
+            aseprite,
+            frame,
+            *fill,
+            |slice, center| snap.geometry(pixel_scale, slice, center),
+        );
+    }
+}
+
+/// Despawns the children of UI nodes that lost their Aseprite components.
+pub fn cleanup_aseprite_ui_children(
+    mut commands: Commands,
+    mut removed: RemovedComponents<AsepriteUiChildren>,
+    parents: Query<&Children>,
+    ui_children: Query<(), With<AsepriteUiChild>>,
+    mut orphaned: Query<
+        &mut AsepriteUiChildren,
+        Or<(Without<AsepriteAtlas>, Without<Handle<Aseprite>>)>,
+    >,
+) {
+    for entity in removed.iter() {
+        let children = coalesce!(parents.get(entity).ok(), continue);
+        for child in children.iter() {
+            if ui_children.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+    }
+    for mut children in orphaned.iter_mut() {
+        if children.entities().is_empty() {
+            continue;
+        }
+        for child in children.entities() {
+            commands.entity(*child).despawn_recursive();
+        }
+        *children = AsepriteUiChildren::NoChild;
+    }
+}
+
+fn spawn_ui_children(
+    commands: &mut Commands,
+    entity: Entity,
+    atlas: &AsepriteAtlas,
+    aseprite_handle: &Handle<Aseprite>,
+    aseprite: &Aseprite,
+    frame: usize,
+    fill: NinepatchFill,
+    geometry: impl Fn(&SliceSegment, Rect) -> (Vec2, Rect),
+) -> AsepriteUiChildren {
+    let ninepatch = atlas.slice.and_then(|slice_name| {
+        let slice = aseprite.slice(slice_name, frame);
+        slice.ninepatch_center.map(|center| (slice, center))
+    });
+    if let Some((slice, ninepatch_center)) = ninepatch {
+        let (size, ninepatch_center) = geometry(slice, ninepatch_center);
+        let mut ninepatches = [entity; 9];
+        for i in 0u8..9 {
+            let style = ninepatch_ui_style(i, size, ninepatch_center);
+            if fill == NinepatchFill::Tile && (i % 3 == 1 || i / 3 == 1) {
+                let tile_size = ninepatch_ui_tile_size(i, size, ninepatch_center);
+                ninepatches[i as usize] = commands
+                    .spawn((
+                        NodeBundle {
+                            style: Style {
+                                overflow: Overflow::clip(),
+                                ..style
                             },
                             z_index: ZIndex::Local(-1),
-                            aseprite: aseprite_handle.clone(),
-                            aseprite_atlas: AsepriteAtlas { ninepatch: Some(i), ..atlas.clone() },
-                            texture_atlas: aseprite.atlas.clone(),
                             ..Default::default()
-                        }).id();
-                    }
-                    commands.entity(entity).push_children(&ninepatches).id();
-                    AsepriteUiChildren::Ninepatches(ninepatches)
-                } else {
-                    let child = commands.spawn(AsepriteUiChildBundle {
-                        aseprite: aseprite_handle.clone(),
-                        aseprite_atlas: atlas.clone(),
-                        texture_atlas: aseprite.atlas.clone(),
-                        style: Style {
-                            width: Val::Percent(100.0),
-                            height: Val::Percent(100.0),
-                            position_type: PositionType::Absolute,
+                        },
+                        aseprite_handle.clone(),
+                        AsepriteAtlas {
+                            ninepatch: Some(i),
+                            ..atlas.clone()
+                        },
+                        AsepriteUiTiledPiece {
+                            tile_size,
                             ..Default::default()
                         },
-                        ..Default::default()
-                    }).id();
-                    info!("spawn sprite");
-                    commands.entity(entity).add_child(child);
-                    AsepriteUiChildren::Sprite(child)
-                }
-            }
-            AsepriteUiChildren::Sprite(entity) => {
-                if let Ok(mut child_atlas) = children_query.get_mut(entity) {
-                    if child_atlas.tag != atlas.tag { child_atlas.tag = atlas.tag.clone(); }
-                    if child_atlas.slice != atlas.slice { child_atlas.slice = atlas.slice.clone(); }
-                    if child_atlas.layer != atlas.layer { child_atlas.layer = atlas.layer.clone(); }
-                }
+                        AsepriteUiChild,
+                    ))
+                    .id();
                 continue;
             }
-            AsepriteUiChildren::Ninepatches(ninepatches) => {
-                let mut iter = children_query.iter_many_mut(ninepatches.iter());
-                while let Some(mut child_atlas) = iter.fetch_next() {
-                    if child_atlas.tag != atlas.tag { child_atlas.tag = atlas.tag.clone(); }
-                    if child_atlas.slice != atlas.slice { child_atlas.slice = atlas.slice.clone(); }
-                    if child_atlas.layer != atlas.layer { child_atlas.layer = atlas.layer.clone(); }
+            ninepatches[i as usize] = commands
+                .spawn(AsepriteUiChildBundle {
+                    style,
+                    z_index: ZIndex::Local(-1),
+                    aseprite: aseprite_handle.clone(),
+                    aseprite_atlas: AsepriteAtlas {
+                        ninepatch: Some(i),
+                        ..atlas.clone()
+                    },
+                    texture_atlas: aseprite.atlas.clone(),
+                    ..Default::default()
+                })
+                .id();
+        }
+        commands.entity(entity).push_children(&ninepatches);
+        AsepriteUiChildren::Ninepatches(ninepatches)
+    } else {
+        let child = commands
+            .spawn(AsepriteUiChildBundle {
+                aseprite: aseprite_handle.clone(),
+                aseprite_atlas: atlas.clone(),
+                texture_atlas: aseprite.atlas.clone(),
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .id();
+        commands.entity(entity).add_child(child);
+        AsepriteUiChildren::Sprite(child)
+    }
+}
+
+fn ninepatch_ui_style(i: u8, size: Vec2, center: Rect) -> Style {
+    Style {
+        position_type: PositionType::Absolute,
+        top: match i / 3 {
+            0 => Val::Px(0.0),
+            1 => Val::Px(center.min.y),
+            _ => Val::Auto,
+        },
+        bottom: match i / 3 {
+            0 => Val::Auto,
+            1 => Val::Px(size.y - center.max.y),
+            _ => Val::Px(0.0),
+        },
+        left: match i % 3 {
+            0 => Val::Px(0.0),
+            1 => Val::Px(center.min.x),
+            _ => Val::Auto,
+        },
+        right: match i % 3 {
+            0 => Val::Auto,
+            1 => Val::Px(size.x - center.max.x),
+            _ => Val::Px(0.0),
+        },
+        height: match i / 3 {
+            0 => Val::Px(center.min.y),
+            1 => Val::Auto,
+            _ => Val::Px(size.y - center.max.y),
+        },
+        width: match i % 3 {
+            0 => Val::Px(center.min.x),
+            1 => Val::Auto,
+            _ => Val::Px(size.x - center.max.x),
+        },
+        ..Default::default()
+    }
+}
+
+fn ninepatch_ui_tile_size(i: u8, size: Vec2, center: Rect) -> Vec2 {
+    Vec2::new(
+        match i % 3 {
+            0 => center.min.x,
+            1 => center.width(),
+            _ => size.x - center.max.x,
+        },
+        match i / 3 {
+            0 => center.min.y,
+            1 => center.height(),
+            _ => size.y - center.max.y,
+        },
+    )
+}
+
+/// Re-lays out ninepatch children when the parent's animation reaches a slice key with
+/// different ninepatch geometry.
+pub fn layout_ninepatch_ui(
+    aseprites: Res<Assets<Aseprite>>,
+    query: Query<
+        (
+            &AsepriteUiChildren,
+            &AsepriteAtlas,
+            &AsepriteAnimation,
+            &Handle<Aseprite>,
+            Option<&AsepriteUiPixelScale>,
+        ),
+        (Changed<AsepriteAnimation>, Without<AsepriteUiChild>),
+    >,
+    mut children_query: Query<
+        (&mut Style, Option<&mut AsepriteUiTiledPiece>),
+        With<AsepriteUiChild>,
+    >,
+    snap: NinepatchUiSnap,
+) {
+    for (children, atlas, ase_anim, aseprite_handle, pixel_scale) in query.iter() {
+        let AsepriteUiChildren::Ninepatches(ninepatches) = children else {
+            continue;
+        };
+        let slice_name = coalesce!(atlas.slice, continue);
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let slice = aseprite.slice(slice_name, ase_anim.current_frame() as usize);
+        let ninepatch_center = coalesce!(slice.ninepatch_center, continue);
+        let (size, ninepatch_center) = snap.geometry(pixel_scale, slice, ninepatch_center);
+        for (i, child) in ninepatches.iter().enumerate() {
+            let (mut style, piece) = coalesce!(children_query.get_mut(*child).ok(), continue);
+            let layout = ninepatch_ui_style(i as u8, size, ninepatch_center);
+            if style.top != layout.top
+                || style.bottom != layout.bottom
+                || style.left != layout.left
+                || style.right != layout.right
+                || style.width != layout.width
+                || style.height != layout.height
+            {
+                style.top = layout.top;
+                style.bottom = layout.bottom;
+                style.left = layout.left;
+                style.right = layout.right;
+                style.width = layout.width;
+                style.height = layout.height;
+            }
+            if let Some(mut piece) = piece {
+                let tile_size = ninepatch_ui_tile_size(i as u8, size, ninepatch_center);
+                if piece.tile_size != tile_size {
+                    piece.tile_size = tile_size;
+                    piece.tiles = 0;
                 }
+            }
+        }
+    }
+}
+
+// Ninepatch borders, in the same units as the ninepatch pieces.
+fn ninepatch_ui_borders(size: Vec2, center: Rect) -> UiRect {
+    UiRect {
+        left: Val::Px(center.min.x),
+        right: Val::Px(size.x - center.max.x),
+        top: Val::Px(center.min.y),
+        bottom: Val::Px(size.y - center.max.y),
+    }
+}
+
+pub fn apply_ninepatch_ui_layout(
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<
+        (
+            &AsepriteUiLayout,
+            &AsepriteAtlas,
+            &AsepriteAnimation,
+            &Handle<Aseprite>,
+            &mut Style,
+            Option<&AsepriteUiPixelScale>,
+        ),
+        (
+            Or<(Changed<AsepriteAnimation>, Changed<AsepriteUiLayout>)>,
+            Without<AsepriteUiChild>,
+        ),
+    >,
+    snap: NinepatchUiSnap,
+) {
+    for (layout, atlas, ase_anim, aseprite_handle, mut style, pixel_scale) in query.iter_mut() {
+        if !layout.padding && !layout.min_size {
+            continue;
+        }
+        let slice_name = coalesce!(atlas.slice, continue);
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let slice = aseprite.slice(slice_name, ase_anim.current_frame() as usize);
+        let ninepatch_center = coalesce!(slice.ninepatch_center, continue);
+        let (size, ninepatch_center) = snap.geometry(pixel_scale, slice, ninepatch_center);
+        let borders = ninepatch_ui_borders(size, ninepatch_center);
+        if layout.padding && style.padding != borders {
+            style.padding = borders;
+        }
+        if layout.min_size {
+            let min_width = Val::Px(size.x - ninepatch_center.width());
+            let min_height = Val::Px(size.y - ninepatch_center.height());
+            if style.min_width != min_width {
+                style.min_width = min_width;
+            }
+            if style.min_height != min_height {
+                style.min_height = min_height;
+            }
+        }
+    }
+}
+
+// Columns and rows of tiles covering `size`, the last ones partially.
+fn ninepatch_ui_tile_grid(size: Vec2, tile_size: Vec2) -> (usize, usize) {
+    (
+        (size.x / tile_size.x).ceil() as usize,
+        (size.y / tile_size.y).ceil() as usize,
+    )
+}
+
+// Tiles sit at their grid cell instead of flowing, so the clipping container cuts the last
+// column and row rather than wrapping them.
+fn ninepatch_ui_tile_style(index: usize, columns: usize, tile_size: Vec2) -> Style {
+    Style {
+        position_type: PositionType::Absolute,
+        left: Val::Px((index % columns) as f32 * tile_size.x),
+        top: Val::Px((index / columns) as f32 * tile_size.y),
+        width: Val::Px(tile_size.x),
+        height: Val::Px(tile_size.y),
+        ..Default::default()
+    }
+}
+
+/// Keeps one tile child per `tile_size` cell of a tiled piece, reusing the existing tiles and
+/// only spawning or despawning the difference.
+pub fn tile_ninepatch_ui(
+    mut commands: Commands,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<
+        (
+            Entity,
+            &Node,
+            Ref<AsepriteAtlas>,
+            &Handle<Aseprite>,
+            &mut AsepriteUiTiledPiece,
+            Option<&Children>,
+        ),
+        Or<(
+            Changed<Node>,
+            Changed<AsepriteAtlas>,
+            Changed<AsepriteUiTiledPiece>,
+        )>,
+    >,
+    mut tiles_query: Query<
+        (&mut Style, &mut AsepriteAtlas),
+        (With<AsepriteUiChild>, Without<AsepriteUiTiledPiece>),
+    >,
+) {
+    for (entity, node, atlas, aseprite_handle, mut piece, children) in query.iter_mut() {
+        if piece.tile_size.x <= 0.0 || piece.tile_size.y <= 0.0 {
+            continue;
+        }
+        let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+        let (columns, rows) = ninepatch_ui_tile_grid(node.size(), piece.tile_size);
+        if piece.tiles == columns * rows && piece.columns == columns && !atlas.is_changed() {
+            continue;
+        }
+        let mut existing = 0;
+        for child in children.into_iter().flatten() {
+            let (mut style, mut tile_atlas) = coalesce!(tiles_query.get_mut(*child).ok(), continue);
+            if existing == columns * rows {
+                commands.entity(*child).despawn_recursive();
                 continue;
             }
+            let layout = ninepatch_ui_tile_style(existing, columns, piece.tile_size);
+            existing += 1;
+            if style.left != layout.left
+                || style.top != layout.top
+                || style.width != layout.width
+                || style.height != layout.height
+            {
+                style.position_type = layout.position_type;
+                style.left = layout.left;
+                style.top = layout.top;
+                style.width = layout.width;
+                style.height = layout.height;
+            }
+            if *tile_atlas != *atlas {
+                *tile_atlas = atlas.clone();
+            }
         }
+        let tiles: Vec<_> = (existing..columns * rows)
+            .map(|index| {
+                commands
+                    .spawn(AsepriteUiChildBundle {
+                        style: ninepatch_ui_tile_style(index, columns, piece.tile_size),
+                        aseprite: aseprite_handle.clone(),
+                        aseprite_atlas: atlas.clone(),
+                        texture_atlas: aseprite.atlas.clone(),
+                        ..Default::default()
+                    })
+                    .id()
+            })
+            .collect();
+        commands.entity(entity).push_children(&tiles);
+        piece.tiles = columns * rows;
+        piece.columns = columns;
+    }
+}
+
+pub fn update_aseprite_ui_button(
+    time: Res<Time>,
+    aseprites: Res<Assets<Aseprite>>,
+    mut query: Query<
+        (
+            Ref<Interaction>,
+            &mut AsepriteUiButton,
+            &mut AsepriteAtlas,
+            &Handle<Aseprite>,
+            Option<&AsepriteUiDisabled>,
+        ),
+        Without<AsepriteUiChild>,
+    >,
+) {
+    for (interaction, mut button, mut atlas, aseprite_handle, disabled) in query.iter_mut() {
+        let disabled = disabled.is_some();
+        button.press_remaining = button.press_remaining.saturating_sub(time.delta());
+        if interaction.is_changed() && *interaction == Interaction::Pressed && !disabled {
+            if let Some(tag) = button.press_animation {
+                let aseprite = coalesce!(aseprites.get(aseprite_handle), continue);
+                button.press_remaining = aseprite
+                    .frame_range(Some(tag))
+                    .map(|frame| aseprite.frame_duration(frame as usize))
+                    .sum();
+            }
+        }
+        let mut style = button.style(*interaction, disabled);
+        if !disabled && !button.press_remaining.is_zero() {
+            style.tag = button.press_animation;
+        }
+        if atlas.tag != style.tag {
+            atlas.tag = style.tag;
+        }
+        if atlas.slice != style.slice {
+            atlas.slice = style.slice;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::fixup_texture_atlas;
+    use crate::test_utils::{asset_app, load_aseprite};
+    use crate::ui::components::AsepriteUiBundle;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Duration;
+
+    // The UI systems as `AsepritePlugin` schedules them, with every update 100ms apart.
+    fn ui_app() -> App {
+        let mut app = asset_app();
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            100,
+        )))
+        .init_resource::<UiScale>()
+        .init_resource::<AsepriteUiPixelScale>()
+        .add_systems(PreUpdate, fixup_texture_atlas)
+        .add_systems(
+            PreUpdate,
+            fixup_aseprite_animation_ui.after(fixup_texture_atlas),
+        )
+        .add_systems(
+            PreUpdate,
+            fixup_ninepatch_ui.after(fixup_aseprite_animation_ui),
+        )
+        .add_systems(PreUpdate, cleanup_aseprite_ui_children)
+        .add_systems(Update, animate_aseprite_ui);
+        app
+    }
+
+    fn spawn_ui(app: &mut App, aseprite: &Handle<Aseprite>, atlas: AsepriteAtlas) -> Entity {
+        app.world
+            .spawn(AsepriteUiBundle {
+                aseprite: aseprite.clone(),
+                aseprite_atlas: atlas,
+                ..Default::default()
+            })
+            .id()
+    }
+
+    fn ui_children(app: &App, entity: Entity) -> Vec<Entity> {
+        app.world
+            .get::<AsepriteUiChildren>(entity)
+            .unwrap()
+            .entities()
+            .to_vec()
+    }
+
+    #[test]
+    fn fixup_spawns_sprite_or_ninepatch_children() {
+        let mut app = ui_app();
+        let aseprite = load_aseprite(&mut app, "ui.aseprite");
+        let sprite = spawn_ui(
+            &mut app,
+            &aseprite,
+            AsepriteAtlas {
+                slice: Some("ThoughtBubble"),
+                ..Default::default()
+            },
+        );
+        let ninepatch = spawn_ui(
+            &mut app,
+            &aseprite,
+            AsepriteAtlas {
+                slice: Some("SpeechBubble"),
+                ..Default::default()
+            },
+        );
+        app.update();
+
+        let sprite_children = ui_children(&app, sprite);
+        assert!(matches!(
+            app.world.get::<AsepriteUiChildren>(sprite),
+            Some(AsepriteUiChildren::Sprite(_))
+        ));
+        assert_eq!(
+            app.world.get::<Children>(sprite).unwrap().to_vec(),
+            sprite_children
+        );
+        assert_eq!(
+            app.world
+                .get::<AsepriteAtlas>(sprite_children[0])
+                .unwrap()
+                .ninepatch,
+            None
+        );
+
+        let pieces = ui_children(&app, ninepatch);
+        assert_eq!(pieces.len(), 9);
+        assert_eq!(
+            app.world.get::<Children>(ninepatch).unwrap().to_vec(),
+            pieces
+        );
+        for (i, piece) in pieces.iter().enumerate() {
+            let atlas = app.world.get::<AsepriteAtlas>(*piece).unwrap();
+            assert_eq!(atlas.slice, Some("SpeechBubble"));
+            assert_eq!(atlas.ninepatch, Some(i as u8));
+        }
+        // The SpeechBubble center starts at (14, 16) and ends 14 and 16 pixels from the corner.
+        let top_left = app.world.get::<Style>(pieces[0]).unwrap();
+        assert_eq!(
+            (top_left.width, top_left.height),
+            (Val::Px(14.0), Val::Px(16.0))
+        );
+        let bottom_right = app.world.get::<Style>(pieces[8]).unwrap();
+        assert_eq!(
+            (bottom_right.width, bottom_right.height),
+            (Val::Px(14.0), Val::Px(16.0))
+        );
+
+        // Switching to a slice without ninepatch data replaces the pieces with one sprite.
+        app.world.get_mut::<AsepriteAtlas>(ninepatch).unwrap().slice = Some("ThoughtBubble");
+        app.update();
+
+        assert!(matches!(
+            app.world.get::<AsepriteUiChildren>(ninepatch),
+            Some(AsepriteUiChildren::Sprite(_))
+        ));
+        assert!(pieces
+            .iter()
+            .all(|piece| app.world.get_entity(*piece).is_none()));
+        assert_eq!(app.world.get::<Children>(ninepatch).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn animate_steps_ui_sprites_and_parents() {
+        let mut app = ui_app();
+        let handle = load_aseprite(&mut app, "characters.aseprite");
+        let walk = app
+            .world
+            .resource::<Assets<Aseprite>>()
+            .get(&handle)
+            .unwrap()
+            .atlas_range(None, Some("Walk"), None, None)
+            .unwrap();
+        let atlas = AsepriteAtlas {
+            tag: Some("Walk"),
+            ..Default::default()
+        };
+        let sprite = app
+            .world
+            .spawn(AsepriteUiChildBundle {
+                aseprite: handle.clone(),
+                aseprite_atlas: atlas.clone(),
+                ..Default::default()
+            })
+            .id();
+        let parent = spawn_ui(&mut app, &handle, atlas);
+
+        // Each frame lasts 100ms: the first update starts the tag, the next ones advance it.
+        for step in 0..3 {
+            app.update();
+            let index = app.world.get::<UiTextureAtlasImage>(sprite).unwrap().index;
+            assert_eq!(index, (walk.start + step) as usize);
+            let animation = app.world.get::<AsepriteAnimation>(parent).unwrap();
+            assert_eq!(animation.current_frame(), 1 + step);
+        }
+    }
+
+    #[test]
+    fn nodes_spawned_before_load_play_their_tag() {
+        let mut app = ui_app();
+        let handle = app
+            .world
+            .resource::<AssetServer>()
+            .load("characters.aseprite");
+        let parent = spawn_ui(
+            &mut app,
+            &handle,
+            AsepriteAtlas {
+                tag: Some("Walk"),
+                ..Default::default()
+            },
+        );
+        load_aseprite(&mut app, "characters.aseprite");
+        for _ in 0..3 {
+            app.update();
+        }
+
+        let animation = app.world.get::<AsepriteAnimation>(parent).unwrap();
+        assert_eq!(animation.frame_range, 1..9);
+        assert!(animation.current_frame() > 1);
+        assert!(matches!(
+            app.world.get::<AsepriteUiChildren>(parent),
+            Some(AsepriteUiChildren::Sprite(_))
+        ));
+    }
+
+    #[test]
+    fn tiles_past_the_edge_are_clipped_in_place() {
+        // 10x5 is not a multiple of 4x4: the third column and the second row stick out of the
+        // container instead of wrapping onto new rows.
+        let tile_size = Vec2::new(4.0, 4.0);
+        let (columns, rows) = ninepatch_ui_tile_grid(Vec2::new(10.0, 5.0), tile_size);
+        assert_eq!((columns, rows), (3, 2));
+        let positions: Vec<_> = (0..columns * rows)
+            .map(|index| {
+                let style = ninepatch_ui_tile_style(index, columns, tile_size);
+                assert_eq!(style.position_type, PositionType::Absolute);
+                assert_eq!((style.width, style.height), (Val::Px(4.0), Val::Px(4.0)));
+                (style.left, style.top)
+            })
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                (Val::Px(0.0), Val::Px(0.0)),
+                (Val::Px(4.0), Val::Px(0.0)),
+                (Val::Px(8.0), Val::Px(0.0)),
+                (Val::Px(0.0), Val::Px(4.0)),
+                (Val::Px(4.0), Val::Px(4.0)),
+                (Val::Px(8.0), Val::Px(4.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn cleanup_despawns_children_of_stripped_nodes() {
+        let mut app = ui_app();
+        let aseprite = load_aseprite(&mut app, "ui.aseprite");
+        let atlas = AsepriteAtlas {
+            slice: Some("SpeechBubble"),
+            ..Default::default()
+        };
+        let without_handle = spawn_ui(&mut app, &aseprite, atlas.clone());
+        let without_children = spawn_ui(&mut app, &aseprite, atlas);
+        app.update();
+        let first = ui_children(&app, without_handle);
+        let second = ui_children(&app, without_children);
+        assert_eq!((first.len(), second.len()), (9, 9));
+
+        app.world
+            .entity_mut(without_handle)
+            .remove::<Handle<Aseprite>>();
+        app.world
+            .entity_mut(without_children)
+            .remove::<AsepriteUiChildren>();
+        app.update();
+
+        assert!(first
+            .iter()
+            .chain(&second)
+            .all(|child| app.world.get_entity(*child).is_none()));
+        assert!(ui_children(&app, without_handle).is_empty());
     }
 }
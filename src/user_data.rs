@@ -0,0 +1,183 @@
+use crate::utils::coalesce;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+const HEADER_SIZE: usize = 128;
+const FRAME_HEADER_SIZE: usize = 16;
+const CHUNK_HEADER_SIZE: usize = 6;
+const USER_DATA_CHUNK: u16 = 0x2020;
+const TILESET_CHUNK: u16 = 0x2023;
+
+/// User data authored on a tile in the Aseprite tileset editor.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct AsepriteTileUserData {
+    pub text: Option<String>,
+    pub color: Option<Color>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn string(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        Some(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+// `asefile` does not expose tile user data, so the chunks are read straight from the file.
+// Aseprite writes the tileset's own user data chunk right after the tileset chunk, followed by
+// one user data chunk per tile. Only tilesets in `tile_counts`, the counts `asefile` parsed, are
+// read, and never for more tiles than that.
+pub(crate) fn read_tile_user_data(
+    bytes: &[u8],
+    tile_counts: &HashMap<u32, u32>,
+) -> HashMap<u32, Vec<Option<AsepriteTileUserData>>> {
+    let mut result = HashMap::new();
+    let mut reader = Reader {
+        bytes,
+        pos: HEADER_SIZE,
+    };
+    let num_frames = Reader { bytes, pos: 6 }.u16().unwrap_or(0);
+    for _ in 0..num_frames {
+        let frame_start = reader.pos;
+        let frame_size = coalesce!(reader.u32(), break) as usize;
+        reader.pos = frame_start + 6;
+        let old_chunks = coalesce!(reader.u16(), break) as u32;
+        reader.pos = frame_start + 12;
+        let new_chunks = coalesce!(reader.u32(), break);
+        reader.pos = frame_start + FRAME_HEADER_SIZE;
+        let num_chunks = if new_chunks == 0 {
+            old_chunks
+        } else {
+            new_chunks
+        };
+
+        // (tileset id, number of user data chunks seen since the tileset chunk)
+        let mut current: Option<(u32, u32)> = None;
+        for _ in 0..num_chunks {
+            let chunk_start = reader.pos;
+            let chunk_size = coalesce!(reader.u32(), break) as usize;
+            let chunk_type = coalesce!(reader.u16(), break);
+            match chunk_type {
+                TILESET_CHUNK => {
+                    current = None;
+                    if let (Some(id), Some(_flags), Some(num_tiles)) =
+                        (reader.u32(), reader.u32(), reader.u32())
+                    {
+                        if let Some(tile_count) = tile_counts.get(&id) {
+                            result.insert(id, vec![None; num_tiles.min(*tile_count) as usize]);
+                            current = Some((id, 0));
+                        }
+                    }
+                }
+                USER_DATA_CHUNK => {
+                    if let Some((id, seen)) = current.as_mut() {
+                        // The first chunk belongs to the tileset itself.
+                        if *seen > 0 {
+                            let tiles = result.get_mut(id).unwrap();
+                            if let Some(tile) = tiles.get_mut(*seen as usize - 1) {
+                                *tile = read_user_data(&mut reader);
+                            }
+                        }
+                        *seen += 1;
+                    }
+                }
+                _ => current = None,
+            }
+            reader.pos = chunk_start + chunk_size.max(CHUNK_HEADER_SIZE);
+        }
+        reader.pos = frame_start + frame_size.max(FRAME_HEADER_SIZE);
+    }
+    result
+}
+
+fn read_user_data(reader: &mut Reader) -> Option<AsepriteTileUserData> {
+    let flags = reader.u32()?;
+    let text = if flags & 1 != 0 {
+        Some(reader.string()?)
+    } else {
+        None
+    };
+    let color = if flags & 2 != 0 {
+        let (r, g, b, a) = (reader.u8()?, reader.u8()?, reader.u8()?, reader.u8()?);
+        Some(Color::rgba_u8(r, g, b, a))
+    } else {
+        None
+    };
+    if text.is_none() && color.is_none() {
+        return None;
+    }
+    Some(AsepriteTileUserData { text, color })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::read_asefile;
+
+    const FIXTURE: &str = "tests/tile_user_data.aseprite";
+
+    fn fixture_bytes() -> Vec<u8> {
+        std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("assets")
+                .join(FIXTURE),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_user_data_of_each_tile() {
+        // Tileset 0 has four tiles: no user data, text, a color, and both.
+        let asefile = read_asefile(FIXTURE);
+        let tile_counts = asefile
+            .tilesets()
+            .iter()
+            .map(|(id, tileset)| (id.value(), tileset.tile_count()))
+            .collect();
+        let user_data = read_tile_user_data(&fixture_bytes(), &tile_counts);
+        assert_eq!(
+            user_data[&0],
+            vec![
+                None,
+                Some(AsepriteTileUserData {
+                    text: Some("solid".to_owned()),
+                    color: None,
+                }),
+                Some(AsepriteTileUserData {
+                    text: None,
+                    color: Some(Color::rgba_u8(255, 0, 0, 255)),
+                }),
+                Some(AsepriteTileUserData {
+                    text: Some("water".to_owned()),
+                    color: Some(Color::rgba_u8(0, 0, 255, 128)),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn clamps_tiles_to_parsed_count() {
+        let user_data = read_tile_user_data(&fixture_bytes(), &HashMap::from([(0, 2)]));
+        assert_eq!(user_data[&0].len(), 2);
+        assert!(read_tile_user_data(&fixture_bytes(), &HashMap::new()).is_empty());
+    }
+}